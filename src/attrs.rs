@@ -0,0 +1,114 @@
+// Support for the attributes which can be placed on individual items to control the plugin (e.g.,
+// `#[disable_code::tag("editor_only")]`, or `#[disable_code::keep]`, which exempts an item and
+// everything inside of it from filtering, and `#[disable_code::remove]`, which removes an item
+// whatever the filter).
+//
+// Each attribute can be written either with a path (`#[disable_code::<name>]`) or, for positions
+// where attributes with paths are not accepted, as a single registered name
+// (`#[disable_code_<name>]`). Both forms are stripped from the crate after filtering so that the
+// rest of the compiler never sees them.
+
+use syntax::ast::{Attribute, Item, Mac};
+use syntax::fold::{self, Folder};
+use syntax::parse::token::{Lit, Token};
+use syntax::ptr::P;
+use syntax::tokenstream::{TokenStream, TokenTree};
+
+use dummy_item;
+
+use std::mem;
+
+// The names of the plugin's item attributes.
+pub const NAMES: &[&str] = &["tag", "filter", "keep", "remove"];
+
+// Returns the registered single-name form of the attribute with the given name.
+pub fn registered_name(name: &str) -> String {
+    format!("disable_code_{}", name)
+}
+
+// Returns the name of the plugin attribute that attr is an instance of, if any.
+fn attr_name(attr: &Attribute) -> Option<String> {
+    let segments = &attr.path.segments;
+    if segments.len() == 2 && segments[0].identifier.name.as_str() == "disable_code" {
+        return Some(String::from(&segments[1].identifier.name.as_str() as &str));
+    }
+    if segments.len() == 1 {
+        let interned = segments[0].identifier.name.as_str();
+        let name: &str = &interned;
+        if name.starts_with("disable_code_") {
+            return Some(String::from(&name["disable_code_".len()..]));
+        }
+    }
+    None
+}
+
+// Returns true if attr is the plugin attribute with the given name.
+pub fn is_attr(attr: &Attribute, name: &str) -> bool {
+    attr_name(attr).map_or(false, |n| n == name)
+}
+
+// Returns true if attrs include `#[disable_code::keep]`.
+pub fn is_kept(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| is_attr(attr, "keep"))
+}
+
+// Returns true if attrs include `#[disable_code::remove]`.
+pub fn is_removed(attrs: &[Attribute]) -> bool {
+    attrs.iter().any(|attr| is_attr(attr, "remove"))
+}
+
+// Returns the string literal arguments of every instance of the plugin attribute with the given
+// name in attrs (e.g., `editor_only` and `diagnostics` for
+// `#[disable_code::tag("editor_only", "diagnostics")]`).
+pub fn str_args(attrs: &[Attribute], name: &str) -> Vec<String> {
+    let mut args = Vec::new();
+    for attr in attrs.iter().filter(|attr| is_attr(attr, name)) {
+        collect_str_literals(attr.tokens.clone(), &mut args);
+    }
+    args
+}
+
+fn collect_str_literals(stream: TokenStream, out: &mut Vec<String>) {
+    for tree in stream.trees() {
+        match tree {
+            TokenTree::Token(_, Token::Literal(Lit::Str_(s), _)) |
+            TokenTree::Token(_, Token::Literal(Lit::StrRaw(s, _), _)) => {
+                out.push(String::from(&s.as_str() as &str));
+            }
+            TokenTree::Delimited(_, delimited) => collect_str_literals(delimited.stream(), out),
+            _ => {}
+        }
+    }
+}
+
+// Removes every plugin attribute from the item and, recursively, from everything it contains.
+pub fn strip(item: &mut Item) {
+    strip_where(item, |attr| attr_name(attr).is_some());
+}
+
+// Removes the attributes for which pred returns true from the item and, recursively, from
+// everything it contains (including items, fields, and expressions).
+pub fn strip_where<F: FnMut(&Attribute) -> bool>(item: &mut Item, pred: F) {
+    let it = mem::replace(item, dummy_item());
+    *item = AttrStripper(pred)
+        .fold_item(P(it))
+        .expect_one("folding an item should produce a single item")
+        .unwrap();
+}
+
+struct AttrStripper<F>(F);
+
+impl<F: FnMut(&Attribute) -> bool> Folder for AttrStripper<F> {
+    fn fold_attribute(&mut self, attr: Attribute) -> Option<Attribute> {
+        if (self.0)(&attr) {
+            None
+        } else {
+            Some(attr)
+        }
+    }
+
+    // The default implementation panics, since most folders run before macros are expanded.
+    fn fold_mac(&mut self, mac: Mac) -> Mac {
+        fold::noop_fold_mac(mac, self)
+    }
+}
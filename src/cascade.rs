@@ -0,0 +1,334 @@
+// A second pass which removes the items that can no longer be compiled because a type they refer
+// to was removed: impls of removed types or traits, and type aliases of removed types. Removing a
+// type alias can make still more items uncompilable, so the pass repeats until nothing changes.
+// The items of trait impls which override removed trait items are removed as well.
+//
+// Paths are resolved by name, relative to the module they appear in and through its imports, so
+// an item is removed if any path in it could refer to a removed type (or to something inside of a
+// removed module).
+
+use syntax::ast::{Block, Item, ItemKind, Mac, Mod, NodeId, Path, Stmt, StmtKind, ViewPath_};
+use syntax::ext::base::ExtCtxt;
+use syntax::visit::{self, Visitor};
+
+use filter::{Context, Node};
+use prune;
+use {cfg_false, is_cfg_false, with_block_mut, with_item_mut, Action, Removal};
+
+use std::collections::{HashMap, HashSet};
+
+// The kinds of removed nodes which other items can refer to as types.
+const TYPE_KINDS: &[&str] = &["struct", "enum", "union", "trait", "type", "mod"];
+
+// The kinds of removed nodes which trait impls can override.
+const TRAIT_ITEM_KINDS: &[&str] = &["fn", "const", "type"];
+
+// Removes (or, for Action::Cfg, disables) every item which refers to a removed type, recording
+// each one in removals.
+pub fn cascade(cx: &ExtCtxt,
+               ctx: &mut Context,
+               action: Action,
+               root: &mut Item,
+               removals: &mut Vec<Removal>) {
+    // a function can't be stubbed out in a way that makes an impl compile again
+    let action = if action == Action::Cfg {
+        Action::Cfg
+    } else {
+        Action::Remove
+    };
+    loop {
+        let removed = Removed {
+            types: removals.iter()
+                .filter(|r| TYPE_KINDS.contains(&r.kind))
+                .map(|r| r.path.clone())
+                .collect(),
+            trait_items: removals.iter()
+                .filter(|r| TRAIT_ITEM_KINDS.contains(&r.kind) && !r.action.replaces())
+                .map(|r| r.path.clone())
+                .collect(),
+        };
+        let before = removals.len();
+        if let ItemKind::Mod(ref mut md) = root.node {
+            cascade_mod(cx, ctx, action, &removed, md, &mut Vec::new(), removals);
+        }
+        if !removals[before..].iter().any(|r| TYPE_KINDS.contains(&r.kind)) {
+            return;
+        }
+    }
+}
+
+// The fully qualified paths of the removed nodes, by what they can make uncompilable.
+struct Removed {
+    // The removed types and modules.
+    types: HashSet<String>,
+    // The removed nodes which may be trait items (and, since they're matched by path, some which
+    // aren't).
+    trait_items: HashSet<String>,
+}
+
+fn cascade_mod(cx: &ExtCtxt,
+               ctx: &mut Context,
+               action: Action,
+               removed: &Removed,
+               md: &mut Mod,
+               path: &mut Vec<String>,
+               removals: &mut Vec<Removal>) {
+    let resolver = Resolver::new(path, md.items.iter().map(|item| &**item), removed);
+    let mut to_delete = Vec::new();
+    for i in 0..md.items.len() {
+        let delete = with_item_mut(md.items.get_mut(i).unwrap(), |it| {
+            cascade_item(cx, ctx, action, &resolver, path, removals, it)
+        });
+
+        if delete {
+            to_delete.push(i);
+        }
+    }
+
+    let mut offset = 0;
+    for i in to_delete {
+        md.items.remove(i - offset);
+        offset += 1;
+    }
+}
+
+// Like cascade_mod, but for the items declared in a function body.
+fn cascade_block(cx: &ExtCtxt,
+                 ctx: &mut Context,
+                 action: Action,
+                 removed: &Removed,
+                 block: &mut Block,
+                 path: &mut Vec<String>,
+                 removals: &mut Vec<Removal>) {
+    let resolver = Resolver::new(path, block.stmts.iter().filter_map(stmt_item), removed);
+    let mut to_delete = Vec::new();
+    for stmt in block.stmts.iter_mut() {
+        let delete = match stmt.node {
+            StmtKind::Item(ref mut item) => {
+                with_item_mut(item,
+                              |it| cascade_item(cx, ctx, action, &resolver, path, removals, it))
+            }
+            _ => false,
+        };
+        to_delete.push(delete);
+    }
+    let mut to_delete = to_delete.into_iter();
+    block.stmts.retain(|_| !to_delete.next().unwrap());
+}
+
+// Removes (or disables) the parts of an item which depend on removed types, recursing into modules
+// and function bodies. Returns true if the item itself should be deleted.
+fn cascade_item(cx: &ExtCtxt,
+                ctx: &mut Context,
+                action: Action,
+                resolver: &Resolver,
+                path: &mut Vec<String>,
+                removals: &mut Vec<Removal>,
+                it: &mut Item)
+                -> bool {
+    // items disabled by the first pass or an earlier iteration are still present
+    if it.attrs.iter().any(is_cfg_false) {
+        return false;
+    }
+    let name = String::from(&it.ident.name.as_str() as &str);
+    match it.node {
+        ItemKind::Mod(ref mut inner) => {
+            ctx.push_mod(&name);
+            path.push(name);
+            cascade_mod(cx, ctx, action, resolver.removed, inner, path, removals);
+            path.pop();
+            ctx.pop_mod();
+            return false;
+        }
+        ItemKind::Fn(_, _, _, _, _, ref mut body) => {
+            ctx.push_fn(&name);
+            path.push(name);
+            with_block_mut(cx, body, |block| {
+                cascade_block(cx, ctx, action, resolver.removed, block, path, removals)
+            });
+            path.pop();
+            ctx.pop_fn();
+            return false;
+        }
+        _ => {}
+    }
+    if !resolver.refers_to_removed(it) {
+        cascade_overrides(cx, ctx, action, resolver, it, removals);
+        return false;
+    }
+    removals.push(Removal::new(ctx, &Node::Item(it), action));
+    if action == Action::Cfg {
+        it.attrs.push(cfg_false(cx, it.span));
+        return false;
+    }
+    true
+}
+
+// Removes (or disables) the items of a trait impl which override removed items of the trait.
+fn cascade_overrides(cx: &ExtCtxt,
+                     ctx: &mut Context,
+                     action: Action,
+                     resolver: &Resolver,
+                     item: &mut Item,
+                     removals: &mut Vec<Removal>) {
+    let (traits, self_ty, items) = match item.node {
+        ItemKind::Impl(_, _, _, _, Some(ref trait_ref), ref self_ty, ref mut items) => {
+            (resolver.candidates(&trait_ref.path), self_ty, items)
+        }
+        _ => return,
+    };
+    ctx.push_impl(self_ty, true);
+    let mut to_delete = Vec::new();
+    for item in items.iter_mut() {
+        let name = item.ident.name.as_str();
+        let overrides_removed = !item.attrs.iter().any(is_cfg_false) &&
+                                traits.iter()
+                                    .map(|t| format!("{}::{}", t.join("::"), name))
+                                    .any(|path| resolver.removed.trait_items.contains(&path));
+        if overrides_removed {
+            removals.push(Removal::new(ctx, &Node::ImplItem(item), action));
+            if action == Action::Cfg {
+                item.attrs.push(cfg_false(cx, item.span));
+            }
+        }
+        to_delete.push(overrides_removed && action != Action::Cfg);
+    }
+    let mut to_delete = to_delete.into_iter();
+    items.retain(|_| !to_delete.next().unwrap());
+    ctx.pop_owner();
+}
+
+// Resolves the paths which appear in a single module.
+struct Resolver<'a> {
+    // The path of the module, as a sequence of module names.
+    module: Vec<String>,
+    // The fully qualified paths imported by the module's `use` declarations, by the names they're
+    // bound to.
+    imports: HashMap<String, Vec<String>>,
+    // The fully qualified paths of the modules whose contents are imported with `*`.
+    globs: Vec<Vec<String>>,
+    // The fully qualified paths of the removed nodes.
+    removed: &'a Removed,
+}
+
+impl<'a> Resolver<'a> {
+    // Constructs a Resolver for the module (or function body) with the given path and items.
+    fn new<'b, I>(module: &[String], items: I, removed: &'a Removed) -> Resolver<'a>
+        where I: Iterator<Item = &'b Item>
+    {
+        let mut imports = HashMap::new();
+        let mut globs = Vec::new();
+        for item in items {
+            let vp = match item.node {
+                ItemKind::Use(ref vp) => vp,
+                _ => continue,
+            };
+            match vp.node {
+                ViewPath_::ViewPathSimple(binding, ref path) => {
+                    let name = String::from(&binding.name.as_str() as &str);
+                    imports.insert(name, prune::resolve(module, path));
+                }
+                ViewPath_::ViewPathGlob(ref path) => globs.push(prune::resolve(module, path)),
+                ViewPath_::ViewPathList(ref prefix, ref items) => {
+                    let base = prune::resolve(module, prefix);
+                    for list_item in items.iter() {
+                        let name = list_item.node.name;
+                        let mut target = base.clone();
+                        let binding = if name.name.as_str() == "self" {
+                            match prefix.segments.last() {
+                                Some(segment) => segment.identifier,
+                                None => name,
+                            }
+                        } else {
+                            target.push(String::from(&name.name.as_str() as &str));
+                            name
+                        };
+                        let binding = list_item.node.rename.unwrap_or(binding);
+                        imports.insert(String::from(&binding.name.as_str() as &str), target);
+                    }
+                }
+            }
+        }
+        Resolver {
+            module: module.to_vec(),
+            imports: imports,
+            globs: globs,
+            removed: removed,
+        }
+    }
+
+    // Returns true if the item is an impl or a type alias which refers to a removed type.
+    fn refers_to_removed(&self, item: &Item) -> bool {
+        let mut paths = PathCollector(Vec::new());
+        match item.node {
+            ItemKind::Impl(_, _, _, ref generics, ref trait_ref, ref self_ty, _) => {
+                paths.visit_generics(generics);
+                if let Some(ref trait_ref) = *trait_ref {
+                    paths.visit_path(&trait_ref.path, trait_ref.ref_id);
+                }
+                paths.visit_ty(self_ty);
+            }
+            ItemKind::DefaultImpl(_, ref trait_ref) => {
+                paths.visit_path(&trait_ref.path, trait_ref.ref_id)
+            }
+            ItemKind::Ty(ref ty, ref generics) => {
+                paths.visit_generics(generics);
+                paths.visit_ty(ty);
+            }
+            _ => return false,
+        }
+        paths.0.iter().any(|path| self.candidates(path).iter().any(|c| self.is_removed(c)))
+    }
+
+    // Returns the fully qualified paths that a path might refer to.
+    fn candidates(&self, path: &Path) -> Vec<Vec<String>> {
+        let names: Vec<String> = path.segments
+            .iter()
+            .map(|segment| String::from(&segment.identifier.name.as_str() as &str))
+            .collect();
+        match names.first().map(|name| name.as_str()) {
+            None => return Vec::new(),
+            Some("{{root}}") | Some("self") | Some("super") => {
+                return vec![prune::resolve(&self.module, path)]
+            }
+            _ => {}
+        }
+
+        let mut candidates = vec![self.module.iter().chain(names.iter()).cloned().collect()];
+        if let Some(target) = self.imports.get(&names[0]) {
+            candidates.push(target.iter().chain(names[1..].iter()).cloned().collect());
+        }
+        for glob in self.globs.iter() {
+            candidates.push(glob.iter().chain(names.iter()).cloned().collect());
+        }
+        candidates
+    }
+
+    // Returns true if the fully qualified path names a removed type or a node inside of a removed
+    // module.
+    fn is_removed(&self, path: &[String]) -> bool {
+        (1..path.len() + 1).any(|i| self.removed.types.contains(&path[..i].join("::")))
+    }
+}
+
+// Returns the item declared by a statement, if any.
+fn stmt_item(stmt: &Stmt) -> Option<&Item> {
+    match stmt.node {
+        StmtKind::Item(ref item) => Some(item),
+        _ => None,
+    }
+}
+
+// Collects every path which appears in the parts of an item it visits.
+struct PathCollector(Vec<Path>);
+
+impl<'a> Visitor<'a> for PathCollector {
+    fn visit_path(&mut self, path: &'a Path, _id: NodeId) {
+        self.0.push(path.clone());
+        visit::walk_path(self, path);
+    }
+
+    // Types written as macro invocations are not expanded yet (and the default implementation
+    // panics).
+    fn visit_mac(&mut self, _mac: &'a Mac) {}
+}
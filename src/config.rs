@@ -0,0 +1,268 @@
+// Support for `disable_code.toml` configuration files, which let every crate in a workspace share
+// a filtering policy. The nearest such file in the crate's manifest directory or any of its
+// ancestors is used. Top-level settings apply to every crate, and can be overridden for a single
+// crate by a table named after it:
+//
+//     filter = "test()"
+//
+//     [my-crate]
+//     filter = "or(test(), bench())"
+//
+// Instead of a filter expression, the filter can be given as a list of rules, each of which
+// matches the nodes satisfying all of its conditions. The first rule that matches a node decides
+// whether it is removed (`action = "remove"`, the default) or kept (`action = "keep"`), and nodes
+// which match no rule are kept:
+//
+//     [[rule]]
+//     kind = ["fn", "mod"]    # any of the given kinds
+//     attrs = ["test"]        # all of the given attributes
+//     action = "remove"
+//
+//     [[my-crate.rule]]
+//     name_regex = "^dbg_"
+//
+// The `mode` and `action` settings take the same values as RUST_DISABLE_CODE_MODE and
+// RUST_DISABLE_CODE_ACTION, which override them.
+
+use parse::{Call, Expr};
+use toml::{self, Table, Value};
+use {Action, Mode};
+
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+pub const FILE_NAME: &str = "disable_code.toml";
+
+// The names of the settings which can appear at the top level or in a crate's table.
+const SETTINGS: &[&str] = &["filter", "rule", "mode", "action"];
+
+// The keys which can appear in a rule.
+const RULE_KEYS: &[&str] = &["kind", "name_regex", "attrs", "action"];
+
+// The settings for a single crate.
+#[derive(Default)]
+pub struct Config {
+    pub filter: Option<String>,
+    // Whether the filter was compiled from a list of rules, rather than written out.
+    pub rules: bool,
+    pub mode: Option<Mode>,
+    pub action: Option<Action>,
+}
+
+// Loads the settings for the crate with the given name from the nearest configuration file. If no
+// file is found, every setting is unset.
+pub fn load(crate_name: &str) -> Result<Config, String> {
+    let path = match find() {
+        Some(path) => path,
+        None => return Ok(Config::default()),
+    };
+    let mut contents = String::new();
+    if let Err(err) = File::open(&path).and_then(|mut f| f.read_to_string(&mut contents)) {
+        return Err(format!("could not read {}: {}", path.display(), err));
+    }
+    let root = toml::parse(&contents).map_err(|err| format!("{}: {}", path.display(), err))?;
+    settings(&root, crate_name).map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+// Returns the path of the nearest configuration file, if any.
+fn find() -> Option<PathBuf> {
+    let mut dir = match env::var_os("CARGO_MANIFEST_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None => return None,
+    };
+    loop {
+        let path = dir.join(FILE_NAME);
+        if path.is_file() {
+            return Some(path);
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+fn settings(root: &Table, crate_name: &str) -> Result<Config, String> {
+    // The compiler's crate names use underscores where the package name may use hyphens, so the
+    // crate's table may be named either way.
+    let package_name = crate_name.replace('_', "-");
+    let own = match root.get(crate_name).or_else(|| root.get(&package_name)) {
+        Some(&Value::Table(ref table)) => Some(table),
+        _ => None,
+    };
+
+    // any other top-level table is for another crate
+    for (key, value) in root.iter() {
+        if value.as_table().is_none() && !SETTINGS.contains(&key.as_str()) {
+            return Err(format!("unrecognized setting: {}", key));
+        }
+    }
+    if let Some(table) = own {
+        for key in table.keys() {
+            if !SETTINGS.contains(&key.as_str()) {
+                return Err(format!("[{}]: unrecognized setting: {}", crate_name, key));
+            }
+        }
+    }
+
+    let mode = match string_setting(root, own, "mode")? {
+        Some(mode) => {
+            match Mode::from_name(&mode) {
+                Some(mode) => Some(mode),
+                None => return Err(format!("unrecognized mode: {}", mode)),
+            }
+        }
+        None => None,
+    };
+    let action = match string_setting(root, own, "action")? {
+        Some(action) => {
+            match Action::from_name(&action) {
+                Some(action) => Some(action),
+                None => return Err(format!("unrecognized action: {}", action)),
+            }
+        }
+        None => None,
+    };
+    let (filter, rules) = filter_setting(root, own)?;
+    Ok(Config {
+           filter: filter,
+           rules: rules,
+           mode: mode,
+           action: action,
+       })
+}
+
+// Returns the filter expression given by either the `filter` setting or the `rule` list, and
+// whether it was the latter. A crate's table overrides both at once, so that it can replace a
+// top-level filter with rules or vice versa.
+fn filter_setting(root: &Table, own: Option<&Table>) -> Result<(Option<String>, bool), String> {
+    let table = match own {
+        Some(table) if table.contains_key("filter") || table.contains_key("rule") => table,
+        _ => root,
+    };
+    match (table.get("filter"), table.get("rule")) {
+        (Some(_), Some(_)) => Err(String::from("filter and rule can't both be set")),
+        (Some(_), None) => string_setting(table, None, "filter").map(|expr| (expr, false)),
+        (None, Some(rules)) => rules_filter(rules).map(|expr| (Some(expr.to_string()), true)),
+        (None, None) => Ok((None, false)),
+    }
+}
+
+// Compiles a list of rules to the equivalent filter expression.
+fn rules_filter(rules: &Value) -> Result<Expr, String> {
+    let rules = match rules.as_array() {
+        Some(rules) => rules,
+        None => return Err(String::from("rule must be an array of tables ([[rule]])")),
+    };
+    // Starting from the last rule, each rule either adds the nodes it matches to those removed by
+    // the rules after it, or takes them away.
+    let mut expr = call("false", Vec::new());
+    for (i, rule) in rules.iter().enumerate().rev() {
+        let (cond, remove) = rule_cond(rule).map_err(|err| format!("rule {}: {}", i + 1, err))?;
+        expr = if remove {
+            call("or", vec![cond, expr])
+        } else {
+            call("and", vec![call("not", vec![cond]), expr])
+        };
+    }
+    Ok(expr)
+}
+
+// Returns the condition under which a rule matches, and whether it removes the nodes it matches.
+fn rule_cond(rule: &Value) -> Result<(Expr, bool), String> {
+    let rule = match rule.as_table() {
+        Some(rule) => rule,
+        None => return Err(format!("expected a table, not a {}", rule.type_name())),
+    };
+    for key in rule.keys() {
+        if !RULE_KEYS.contains(&key.as_str()) {
+            return Err(format!("unrecognized key: {}", key));
+        }
+    }
+
+    let mut conds = Vec::new();
+    if let Some(value) = rule.get("kind") {
+        let kinds = string_list(value, "kind")?;
+        conds.push(combine("or", kinds.into_iter().map(|k| call("kind", vec![k])).collect()));
+    }
+    if let Some(value) = rule.get("name_regex") {
+        match value.as_str() {
+            Some(re) => conds.push(call("regex", vec![Expr::Quote(String::from(re))])),
+            None => return Err(format!("name_regex must be a string, not a {}", value.type_name())),
+        }
+    }
+    if let Some(value) = rule.get("attrs") {
+        let attrs = string_list(value, "attrs")?;
+        conds.push(combine("and", attrs.into_iter().map(|a| call("attr", vec![a])).collect()));
+    }
+    // A rule without conditions would match everything, which is more likely to be a mistake
+    // (e.g., a misspelled key) than intended.
+    if conds.is_empty() {
+        return Err(String::from("a rule must have at least one of kind, name_regex, or attrs"));
+    }
+
+    let remove = match rule.get("action").map(|action| action.as_str()) {
+        None | Some(Some("remove")) => true,
+        Some(Some("keep")) => false,
+        Some(_) => return Err(String::from("action must be \"remove\" or \"keep\"")),
+    };
+    Ok((combine("and", conds), remove))
+}
+
+// Returns the strings given by a setting which may be either a string or an array of strings.
+fn string_list(value: &Value, name: &str) -> Result<Vec<Expr>, String> {
+    let values = match value {
+        &Value::String(_) => vec![value],
+        &Value::Array(ref values) => values.iter().collect(),
+        _ => {
+            return Err(format!("{} must be a string or an array of strings, not a {}",
+                               name,
+                               value.type_name()))
+        }
+    };
+    let mut strings = Vec::new();
+    for value in values {
+        match value.as_str() {
+            Some(s) => strings.push(Expr::Quote(String::from(s))),
+            None => {
+                return Err(format!("{} must only contain strings, not a {}",
+                                   name,
+                                   value.type_name()))
+            }
+        }
+    }
+    if strings.is_empty() {
+        return Err(format!("{} must not be empty", name));
+    }
+    Ok(strings)
+}
+
+// Combines expressions with the given operator, unless there's only one.
+fn combine(op: &str, mut exprs: Vec<Expr>) -> Expr {
+    if exprs.len() == 1 {
+        exprs.pop().unwrap()
+    } else {
+        call(op, exprs)
+    }
+}
+
+fn call(name: &str, args: Vec<Expr>) -> Expr {
+    Expr::Call(Call {
+                   name: String::from(name),
+                   args: args,
+                   offset: 0,
+               })
+}
+
+// Returns the value of a string setting, preferring the crate's own table to the top level.
+fn string_setting(root: &Table, own: Option<&Table>, name: &str) -> Result<Option<String>, String> {
+    let value = match own.and_then(|table| table.get(name)).or_else(|| root.get(name)) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+    match value.as_str() {
+        Some(s) => Ok(Some(String::from(s))),
+        None => Err(format!("{} must be a string, not a {}", name, value.type_name())),
+    }
+}
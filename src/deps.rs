@@ -0,0 +1,117 @@
+// Support for preserving the items which are still referred to by the code that survives
+// filtering. After a tentative pass, any removed item whose name is referred to by a surviving
+// item is preserved, and the pass is repeated, since the preserved items may refer to still more
+// removed items. `use` declarations don't count as references, since prune can remove them
+// instead.
+//
+// As in prune, references are found by name, so an item may be preserved when it didn't need to
+// be, but never the other way around.
+
+use syntax::ast::{Item, ItemKind, Mod};
+use syntax_pos::Span;
+
+use filter::Node;
+use prune;
+use {env_flag, is_cfg_false, Removal};
+
+use std::collections::HashMap;
+
+const ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_PRESERVE_DEPS";
+
+// Returns true if items which are still referred to should be preserved.
+pub fn enabled() -> bool {
+    env_flag(ENV_VAR_NAME)
+}
+
+// A removed item which is still referred to.
+pub struct Dependency {
+    // The fully qualified path of the removed item.
+    pub path: String,
+    pub span: Span,
+    // A description of a surviving item which refers to it (e.g., "`foo::bar`").
+    pub referrer: String,
+}
+
+// Returns the removed items whose names are referred to by the items remaining in root.
+pub fn dependencies(root: &Item, removals: &[Removal]) -> Vec<Dependency> {
+    let mut refs = HashMap::new();
+    if let ItemKind::Mod(ref md) = root.node {
+        collect_refs(md, &mut Vec::new(), &mut refs);
+    }
+
+    let mut deps = Vec::new();
+    for removal in removals {
+        // stubbed out functions and shims still exist
+        if removal.action.replaces() {
+            continue;
+        }
+        let name = removal.path.rsplit("::").next().unwrap_or("");
+        if name == "" {
+            continue;
+        }
+        if let Some(referrer) = refs.get(name) {
+            deps.push(Dependency {
+                          path: removal.path.clone(),
+                          span: removal.span,
+                          referrer: referrer.clone(),
+                      });
+        }
+    }
+    deps
+}
+
+// Maps each name referred to by the items in the module (and, recursively, its submodules) to a
+// description of one of the items referring to it.
+fn collect_refs(md: &Mod, path: &mut Vec<String>, refs: &mut HashMap<String, String>) {
+    for item in md.items.iter() {
+        if item.attrs.iter().any(is_cfg_false) {
+            continue;
+        }
+        let name = String::from(&item.ident.name.as_str() as &str);
+        match item.node {
+            ItemKind::Use(..) |
+            ItemKind::ExternCrate(..) => {}
+            ItemKind::Mod(ref inner) => {
+                path.push(name);
+                collect_refs(inner, path, refs);
+                path.pop();
+            }
+            ItemKind::ForeignMod(ref fm) => {
+                for item in fm.items.iter().filter(|item| !item.attrs.iter().any(is_cfg_false)) {
+                    let name = String::from(&item.ident.name.as_str() as &str);
+                    let referrer = describe(path, &name, "item");
+                    add_refs(refs, &name, &referrer, &Node::ForeignItem(item));
+                }
+            }
+            _ => {
+                let node = Node::Item(item);
+                let referrer = describe(path, &name, node.kind_name());
+                add_refs(refs, &name, &referrer, &node);
+            }
+        }
+    }
+}
+
+// Records the names referred to by a node, other than its own.
+fn add_refs(refs: &mut HashMap<String, String>, name: &str, referrer: &str, node: &Node) {
+    for r in prune::node_refs(node) {
+        if r != name && !refs.contains_key(&r) {
+            refs.insert(r, String::from(referrer));
+        }
+    }
+}
+
+// Describes the item with the given name in the module with the given path, or, if the item has
+// no name (e.g., an impl), describes it by its kind.
+fn describe(path: &[String], name: &str, kind: &str) -> String {
+    if name != "" {
+        let mut path = path.to_vec();
+        path.push(String::from(name));
+        return format!("`{}`", path.join("::"));
+    }
+    if path.is_empty() {
+        format!("an unnamed {} in the crate root", kind)
+    } else {
+        format!("an unnamed {} in `{}`", kind, path.join("::"))
+    }
+}
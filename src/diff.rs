@@ -0,0 +1,88 @@
+// Support for writing the source of the removed nodes as a unified diff, so that reviewers can see
+// exactly what a filter excludes from a build. The hunks have no context lines (as with
+// `diff -U0`), and each one removes every line that a node spans, including any other code which
+// shares its first or last line.
+//
+// Stubbed out functions and shims are left out, since their signatures are kept, as are nodes
+// generated by macros, which have no source of their own.
+
+use syntax::codemap::CodeMap;
+
+use Removal;
+
+use std::ffi::OsStr;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+// The path of a file to append the diff to.
+pub const ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_DIFF";
+
+// The lines of a file spanned by a removed node.
+struct Hunk {
+    file: String,
+    // The number of the first line (counting from 1).
+    first: usize,
+    lines: Vec<String>,
+    // A description of the node (e.g., `fn foo::bar`), which follows the hunk's header.
+    node: String,
+}
+
+// Appends a diff removing the source of every removed node to the file at path.
+pub fn write_diff(path: &OsStr, codemap: &CodeMap, removals: &[Removal]) -> io::Result<()> {
+    let mut hunks = Vec::new();
+    for removal in removals.iter().filter(|r| !r.action.replaces()) {
+        let lo = codemap.lookup_char_pos(removal.span.lo);
+        let hi = codemap.lookup_char_pos(removal.span.hi);
+        if lo.file.name.starts_with('<') || lo.file.name != hi.file.name {
+            continue;
+        }
+        hunks.push(Hunk {
+                       file: lo.file.name.clone(),
+                       first: lo.line,
+                       lines: (lo.line..hi.line + 1)
+                           .map(|line| {
+                                    lo.file.get_line(line - 1).map_or(String::new(), |text| {
+                                        String::from(&*text)
+                                    })
+                                })
+                           .collect(),
+                       node: format!("{} {}", removal.kind, removal.path),
+                   });
+    }
+    hunks.sort_by(|a, b| (&a.file, a.first).cmp(&(&b.file, b.first)));
+
+    let mut diff = String::new();
+    let mut file: Option<String> = None;
+    // the last line removed from the current file, and the number of lines removed from it so far
+    let mut last = 0;
+    let mut removed = 0;
+    for hunk in hunks {
+        if file.as_ref() != Some(&hunk.file) {
+            diff.push_str(&format!("--- a/{}\n+++ b/{}\n", hunk.file, hunk.file));
+            file = Some(hunk.file.clone());
+            last = 0;
+            removed = 0;
+        }
+        // nodes inside of other removed nodes (e.g., the items of a removed impl) are already
+        // covered by the outer node's hunk
+        if hunk.first <= last {
+            continue;
+        }
+        diff.push_str(&format!("@@ -{},{} +{},0 @@ {}\n",
+                               hunk.first,
+                               hunk.lines.len(),
+                               hunk.first - 1 - removed,
+                               hunk.node));
+        for line in hunk.lines.iter() {
+            diff.push_str(&format!("-{}\n", line));
+        }
+        last = hunk.first + hunk.lines.len() - 1;
+        removed += hunk.lines.len();
+    }
+    if diff.is_empty() {
+        return Ok(());
+    }
+    // a single write, as in write_report
+    let mut out = OpenOptions::new().create(true).append(true).open(path)?;
+    out.write_all(diff.as_bytes())
+}
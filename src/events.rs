@@ -0,0 +1,73 @@
+// Support for reporting every removal as a JSON object on a line of its own, for editors and CI
+// bots which annotate code with what the active filter strips. For example:
+//
+//   {"crate":"my_crate","path":"foo::bar","kind":"fn","action":"stub","file":"src/foo.rs",
+//    "lines":[10,14],"filter":"RUST_DISABLE_CODE_FILTER"}
+//
+// (on a single line). `filter` is the origin of the filter which matched the node, or null if the
+// node was removed for some other reason (e.g., because it refers to a removed type).
+
+use syntax::codemap::CodeMap;
+
+use Removal;
+
+use std::ffi::OsStr;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+
+// The path of a file to append the events to, or `-` for stderr.
+pub const ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_EVENTS";
+
+// Appends an event for every removal to the file at path (or writes them to stderr).
+pub fn write_events(path: &OsStr,
+                    codemap: &CodeMap,
+                    crate_name: &str,
+                    removals: &[Removal])
+                    -> io::Result<()> {
+    let mut events = String::new();
+    for removal in removals {
+        let lo = codemap.lookup_char_pos(removal.span.lo);
+        let hi = codemap.lookup_char_pos(removal.span.hi);
+        let filter = match removal.matched_by {
+            Some(ref origin) => quote(origin),
+            None => String::from("null"),
+        };
+        events.push_str(&format!("{{\"crate\":{},\"path\":{},\"kind\":{},\"action\":{},\
+                                  \"file\":{},\"lines\":[{},{}],\"filter\":{}}}\n",
+                                 quote(crate_name),
+                                 quote(&removal.path),
+                                 quote(removal.kind),
+                                 quote(removal.action.name()),
+                                 quote(&lo.file.name),
+                                 lo.line,
+                                 hi.line,
+                                 filter));
+    }
+    if events.is_empty() {
+        return Ok(());
+    }
+    // a single write, as in write_report
+    if path == "-" {
+        return io::stderr().write_all(events.as_bytes());
+    }
+    let mut out = OpenOptions::new().create(true).append(true).open(path)?;
+    out.write_all(events.as_bytes())
+}
+
+// Returns s as a JSON string.
+fn quote(s: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c < ' ' => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
@@ -0,0 +1,124 @@
+use syntax::ast::{Item, ItemKind, Mod, ViewPath_, Visibility};
+
+use prune::resolve;
+
+use std::collections::{HashMap, HashSet};
+
+// The items of a module which can make other items reachable.
+struct ModExports {
+    // The fully qualified paths of the module's `pub` items.
+    children: Vec<String>,
+    // The fully qualified targets of the module's `pub use` declarations, and whether each is a
+    // glob import.
+    reexports: Vec<(String, bool)>,
+}
+
+// Returns the fully qualified paths of the items which are reachable from outside of the crate:
+// `pub` items of the root module, `pub` items of reachable modules, and the targets of `pub use`
+// declarations in reachable modules.
+pub fn exported_paths(root: &Item) -> HashSet<String> {
+    let mut mods = HashMap::new();
+    if let ItemKind::Mod(ref md) = root.node {
+        collect(md, &mut Vec::new(), &mut mods);
+    }
+
+    let mut exported = HashSet::new();
+    let mut visited = HashSet::new();
+    let mut queue = vec![String::new()];
+    while let Some(module) = queue.pop() {
+        if !visited.insert(module.clone()) {
+            continue;
+        }
+        // paths which don't name a module in this crate have nothing more to visit
+        let exports = match mods.get(&module) {
+            Some(exports) => exports,
+            None => continue,
+        };
+        for child in exports.children.iter() {
+            exported.insert(child.clone());
+            queue.push(child.clone());
+        }
+        for &(ref target, glob) in exports.reexports.iter() {
+            // a glob import makes the target's children reachable, but not the target itself
+            if !glob {
+                exported.insert(target.clone());
+            }
+            queue.push(target.clone());
+        }
+    }
+    exported
+}
+
+fn is_pub(vis: &Visibility) -> bool {
+    if let &Visibility::Public = vis {
+        true
+    } else {
+        false
+    }
+}
+
+// Returns the fully qualified path of the item with the given name in the given module.
+fn child_path(module: &[String], name: &str) -> String {
+    let mut path = module.to_vec();
+    path.push(String::from(name));
+    path.join("::")
+}
+
+// Records the exports of the module with the given path and, recursively, of its submodules.
+fn collect(md: &Mod, path: &mut Vec<String>, mods: &mut HashMap<String, ModExports>) {
+    let mut exports = ModExports {
+        children: Vec::new(),
+        reexports: Vec::new(),
+    };
+
+    for item in md.items.iter() {
+        match item.node {
+            ItemKind::Use(ref vp) => {
+                if !is_pub(&item.vis) {
+                    continue;
+                }
+                match vp.node {
+                    ViewPath_::ViewPathSimple(_, ref target) => {
+                        exports.reexports.push((resolve(path, target).join("::"), false));
+                    }
+                    ViewPath_::ViewPathGlob(ref target) => {
+                        exports.reexports.push((resolve(path, target).join("::"), true));
+                    }
+                    ViewPath_::ViewPathList(ref prefix, ref items) => {
+                        let base = resolve(path, prefix);
+                        for list_item in items.iter() {
+                            let mut target = base.clone();
+                            let name = list_item.node.name.name.as_str();
+                            if name != "self" {
+                                target.push(String::from(&name as &str));
+                            }
+                            exports.reexports.push((target.join("::"), false));
+                        }
+                    }
+                }
+            }
+            ItemKind::ForeignMod(ref fm) => {
+                // foreign items live in the namespace of the enclosing module
+                for foreign in fm.items.iter() {
+                    if is_pub(&foreign.vis) {
+                        exports.children.push(child_path(path, &foreign.ident.name.as_str()));
+                    }
+                }
+            }
+            _ => {
+                if is_pub(&item.vis) {
+                    exports.children.push(child_path(path, &item.ident.name.as_str()));
+                }
+            }
+        }
+    }
+
+    for item in md.items.iter() {
+        if let ItemKind::Mod(ref inner) = item.node {
+            path.push(String::from(&item.ident.name.as_str() as &str));
+            collect(inner, path, mods);
+            path.pop();
+        }
+    }
+    mods.insert(path.join("::"), exports);
+}
@@ -1,14 +1,396 @@
-use syntax::ast::{Item, ItemKind};
+use syntax::ast::{Attribute, Block, CrateConfig, Expr as AstExpr, ExprKind, FnDecl, ForeignItem,
+                  ForeignItemKind, FunctionRetTy, Generics, Ident, ImplItem, ImplItemKind, Item,
+                  ItemKind, Mac, MetaItem, MetaItemKind, NestedMetaItem, TraitItem, TraitItemKind,
+                  TraitRef, Ty, TyKind, Unsafety, Variant, Visibility};
 use syntax::attr;
+use syntax::codemap::CodeMap;
+use syntax::ext::base::ExtCtxt;
+use syntax::parse::lexer::comments;
+use syntax::print::pprust;
+use syntax::visit::{self, Visitor};
+use syntax_pos::Span;
 
-use regex::Regex;
-use nom::IResult;
+use attrs;
+use exported;
+use has_default;
+use json;
+use log::{self, Level};
+use parse::{self, Call, Expr, ParseError};
 
+use regex::{self, Regex, RegexBuilder};
+
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::mem;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// The context in which an item is being filtered.
+pub struct Context<'a> {
+    // The names of the modules enclosing the item being filtered, outermost first. The root module
+    // is not included, so top-level items have an empty path.
+    path: Vec<String>,
+    // The names of the modules and functions enclosing the item being filtered, outermost first.
+    // Items declared in a function's body are named as though the function were a module (e.g.,
+    // `foo::Helper` inside of `fn foo`), but aren't inside of another module.
+    scopes: Vec<String>,
+    // The name of the self type of the impl block, or of the trait or enum, whose body contains
+    // the item being filtered, if any.
+    owner: Option<String>,
+    // Whether the owner is an impl of a trait.
+    trait_impl: bool,
+    codemap: &'a CodeMap,
+    // The name of the crate being compiled.
+    crate_name: String,
+    // The cfg options (e.g., `target_os = "linux"`) of the compilation session.
+    config: &'a CrateConfig,
+    // The fully qualified paths of the items which are reachable from outside of the crate.
+    exported: HashSet<String>,
+    // The names of the items which have defaults in each trait defined in the crate, by the
+    // trait's name. Traits with the same name are merged, keeping only the items which have
+    // defaults in all of them.
+    trait_defaults: HashMap<String, HashSet<String>>,
+    // Whether an unsafe_allow_exports() wrapper has matched the node being filtered.
+    exports_allowed: Cell<bool>,
+    // The protected nodes which filters matched, and which were kept anyway.
+    refusals: RefCell<Vec<Refusal>>,
+    // The path of the node most recently matched by a filter, along with the origin of the first
+    // filter which matched it.
+    matched_by: RefCell<Option<(String, String)>>,
+}
+
+// A protected node (see protection), or a node containing one, which a filter matched, but which
+// was kept anyway.
+pub struct Refusal {
+    pub path: String,
+    pub kind: &'static str,
+    pub span: Span,
+    // Why the node (or the one it contains) is protected (e.g., "the crate's entry point").
+    pub reason: &'static str,
+    // A description (e.g., "function `f`") and the span of the protected node inside of the node,
+    // if the node isn't protected itself.
+    pub contains: Option<(String, Span)>,
+    // The filter which matched the node, in normalized form.
+    pub filter: String,
+    // Where the filter which matched the node came from.
+    pub origin: String,
+}
+
+impl<'a> Context<'a> {
+    // Constructs a new Context for filtering the given root module.
+    pub fn new(cx: &ExtCtxt<'a>, root: &Item) -> Context<'a> {
+        Context {
+            path: Vec::new(),
+            scopes: Vec::new(),
+            owner: None,
+            trait_impl: false,
+            codemap: cx.codemap(),
+            crate_name: cx.ecfg.crate_name.clone(),
+            config: &cx.parse_sess.config,
+            exported: exported::exported_paths(root),
+            trait_defaults: trait_defaults(root),
+            exports_allowed: Cell::new(false),
+            refusals: RefCell::new(Vec::new()),
+            matched_by: RefCell::new(None),
+        }
+    }
+
+    // Returns true if implementations of the trait must define the item with the given name. Unless
+    // a trait with the same name is defined in the crate, every item is assumed to be required.
+    pub fn must_define(&self, trait_ref: &TraitRef, name: Ident) -> bool {
+        let trait_name = match trait_ref.path.segments.last() {
+            Some(segment) => String::from(&segment.identifier.name.as_str() as &str),
+            None => return true,
+        };
+        match self.trait_defaults.get(&trait_name) {
+            Some(defaults) => !defaults.contains(&name.name.as_str() as &str),
+            None => true,
+        }
+    }
+
+    // Returns the protected nodes which filters have matched so far.
+    pub fn take_refusals(&mut self) -> Vec<Refusal> {
+        mem::replace(self.refusals.get_mut(), Vec::new())
+    }
+
+    // Returns the origin of the first filter which matched the node, if it's the node most recently
+    // matched.
+    pub fn take_matched_by(&self, node: &Node) -> Option<String> {
+        let path = self.node_path(node);
+        match self.matched_by.borrow_mut().take() {
+            Some((matched, origin)) if matched == path => Some(origin),
+            _ => None,
+        }
+    }
+
+    // Called when descending into the module with the given name.
+    pub fn push_mod(&mut self, name: &str) {
+        self.path.push(String::from(name));
+        self.scopes.push(String::from(name));
+    }
+
+    // Called when leaving the module most recently passed to push_mod.
+    pub fn pop_mod(&mut self) {
+        self.path.pop();
+        self.scopes.pop();
+    }
+
+    // Called when descending into the body of the function with the given name.
+    pub fn push_fn(&mut self, name: &str) {
+        self.scopes.push(String::from(name));
+    }
+
+    // Called when leaving the body of the function most recently passed to push_fn.
+    pub fn pop_fn(&mut self) {
+        self.scopes.pop();
+    }
+
+    // Called when descending into an impl block with the given self type, which implements a trait
+    // if trait_impl is true.
+    pub fn push_impl(&mut self, self_ty: &Ty, trait_impl: bool) {
+        // `impl<T> Foo<T>` contributes `Foo` to the paths of its items
+        let name = match self_ty.node {
+            TyKind::Path(_, ref path) if !path.segments.is_empty() => {
+                String::from(&path.segments.last().unwrap().identifier.name.as_str() as &str)
+            }
+            _ => pprust::ty_to_string(self_ty),
+        };
+        self.owner = Some(name);
+        self.trait_impl = trait_impl;
+    }
+
+    // Called when descending into the definition of the trait or enum with the given name.
+    pub fn push_owner(&mut self, name: &str) {
+        self.owner = Some(String::from(name));
+        self.trait_impl = false;
+    }
+
+    // Called when leaving an impl block or trait or enum definition.
+    pub fn pop_owner(&mut self) {
+        self.owner = None;
+        self.trait_impl = false;
+    }
+
+    // Returns the visibility of the node, like Node::vis, except that the items of a trait impl
+    // have none either, since they're as visible as the trait.
+    pub fn vis<'b>(&self, node: &Node<'b>) -> Option<&'b Visibility> {
+        match node {
+            &Node::ImplItem(_) if self.trait_impl => None,
+            _ => node.vis(),
+        }
+    }
+
+    // Returns the fully qualified path of the module containing the node being filtered.
+    pub fn mod_path(&self) -> String {
+        self.path.join("::")
+    }
+
+    // Returns the fully qualified path of the given node (e.g., `foo::bar::baz`).
+    pub fn node_path(&self, node: &Node) -> String {
+        let mut path = self.scopes.join("::");
+        if let Some(ref ty) = self.owner {
+            if !path.is_empty() {
+                path.push_str("::");
+            }
+            path.push_str(ty);
+        }
+        if !path.is_empty() {
+            path.push_str("::");
+        }
+        path.push_str(&node.ident().name.as_str());
+        path
+    }
+}
+
+// Returns the Context::trait_defaults for the crate.
+fn trait_defaults(root: &Item) -> HashMap<String, HashSet<String>> {
+    let mut collector = TraitCollector(HashMap::new());
+    collector.visit_item(root);
+    collector.0
+}
+
+struct TraitCollector(HashMap<String, HashSet<String>>);
+
+impl<'a> Visitor<'a> for TraitCollector {
+    fn visit_item(&mut self, item: &'a Item) {
+        if let ItemKind::Trait(_, _, _, ref items) = item.node {
+            let defaults: HashSet<String> = items.iter()
+                .filter(|item| has_default(item))
+                .map(|item| String::from(&item.ident.name.as_str() as &str))
+                .collect();
+            let name = String::from(&item.ident.name.as_str() as &str);
+            let merged = match self.0.get(&name) {
+                Some(existing) => existing.intersection(&defaults).cloned().collect(),
+                None => defaults,
+            };
+            self.0.insert(name, merged);
+        }
+        visit::walk_item(self, item);
+    }
+
+    // Traits generated by macros don't exist yet (and the default implementation panics).
+    fn visit_mac(&mut self, _mac: &'a Mac) {}
+}
+
+// A syntax node which can be filtered out from the crate.
+#[derive(Clone, Copy)]
+pub enum Node<'a> {
+    Item(&'a Item),
+    // A declaration inside of an `extern` block.
+    ForeignItem(&'a ForeignItem),
+    // A method, associated const, or associated type inside of an impl block.
+    ImplItem(&'a ImplItem),
+    // A method, associated const, or associated type inside of a trait definition.
+    TraitItem(&'a TraitItem),
+    // A variant of an enum.
+    Variant(&'a Variant),
+}
+
+impl<'a> Node<'a> {
+    pub fn ident(&self) -> Ident {
+        match self {
+            &Node::Item(item) => item.ident,
+            &Node::ForeignItem(item) => item.ident,
+            &Node::ImplItem(item) => item.ident,
+            &Node::TraitItem(item) => item.ident,
+            &Node::Variant(variant) => variant.node.name,
+        }
+    }
+
+    pub fn attrs(&self) -> &'a [Attribute] {
+        match self {
+            &Node::Item(item) => &item.attrs,
+            &Node::ForeignItem(item) => &item.attrs,
+            &Node::ImplItem(item) => &item.attrs,
+            &Node::TraitItem(item) => &item.attrs,
+            &Node::Variant(variant) => &variant.node.attrs,
+        }
+    }
+
+    // Returns the visibility of the node, or None for trait items and enum variants, which have
+    // none of their own.
+    pub fn vis(&self) -> Option<&'a Visibility> {
+        match self {
+            &Node::Item(item) => Some(&item.vis),
+            &Node::ForeignItem(item) => Some(&item.vis),
+            &Node::ImplItem(item) => Some(&item.vis),
+            &Node::TraitItem(_) |
+            &Node::Variant(_) => None,
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            &Node::Item(item) => item.span,
+            &Node::ForeignItem(item) => item.span,
+            &Node::ImplItem(item) => item.span,
+            &Node::TraitItem(item) => item.span,
+            &Node::Variant(variant) => variant.span,
+        }
+    }
+
+    // Returns the kind() name of the node (one of KINDS).
+    pub fn kind_name(&self) -> &'static str {
+        match self {
+            &Node::Item(item) => kind_name(&item.node),
+            &Node::ForeignItem(item) => {
+                match item.node {
+                    ForeignItemKind::Fn(..) => "fn",
+                    ForeignItemKind::Static(..) => "static",
+                }
+            }
+            &Node::ImplItem(item) => {
+                match item.node {
+                    ImplItemKind::Const(..) => "const",
+                    ImplItemKind::Method(..) => "fn",
+                    ImplItemKind::Type(..) => "type",
+                    ImplItemKind::Macro(..) => "macro",
+                }
+            }
+            &Node::TraitItem(item) => {
+                match item.node {
+                    TraitItemKind::Const(..) => "const",
+                    TraitItemKind::Method(..) => "fn",
+                    TraitItemKind::Type(..) => "type",
+                    TraitItemKind::Macro(..) => "macro",
+                }
+            }
+            &Node::Variant(_) => "variant",
+        }
+    }
+
+    // Returns the declaration of the node if it is a function.
+    fn fn_decl(&self) -> Option<&'a FnDecl> {
+        match self {
+            &Node::Item(&Item { node: ItemKind::Fn(ref decl, ..), .. }) |
+            &Node::ForeignItem(&ForeignItem { node: ForeignItemKind::Fn(ref decl, _), .. }) => {
+                Some(decl)
+            }
+            &Node::ImplItem(&ImplItem { node: ImplItemKind::Method(ref sig, _), .. }) |
+            &Node::TraitItem(&TraitItem { node: TraitItemKind::Method(ref sig, _), .. }) => {
+                Some(&sig.decl)
+            }
+            _ => None,
+        }
+    }
+
+    // Returns the body of the node if it is a function with a body.
+    fn fn_body(&self) -> Option<&'a Block> {
+        match self {
+            &Node::Item(&Item { node: ItemKind::Fn(_, _, _, _, _, ref body), .. }) |
+            &Node::ImplItem(&ImplItem { node: ImplItemKind::Method(_, ref body), .. }) |
+            &Node::TraitItem(&TraitItem { node: TraitItemKind::Method(_, Some(ref body)), .. }) => {
+                Some(body)
+            }
+            _ => None,
+        }
+    }
+
+    // Returns the generics of the node, or None if its kind cannot have generic parameters.
+    fn generics(&self) -> Option<&'a Generics> {
+        match self {
+            &Node::Item(item) => item_generics(&item.node),
+            &Node::ForeignItem(item) => {
+                match item.node {
+                    ForeignItemKind::Fn(_, ref generics) => Some(generics),
+                    ForeignItemKind::Static(..) => None,
+                }
+            }
+            &Node::ImplItem(item) => {
+                match item.node {
+                    ImplItemKind::Method(ref sig, _) => Some(&sig.generics),
+                    _ => None,
+                }
+            }
+            &Node::TraitItem(item) => {
+                match item.node {
+                    TraitItemKind::Method(ref sig, _) => Some(&sig.generics),
+                    _ => None,
+                }
+            }
+            &Node::Variant(_) => None,
+        }
+    }
+}
+
+// Visits the fields and discriminant of an enum variant. (visit::walk_variant also requires the
+// enum's generics.)
+pub fn walk_variant<'a, V: Visitor<'a>>(visitor: &mut V, variant: &'a Variant) {
+    for field in variant.node.data.fields() {
+        visitor.visit_struct_field(field);
+    }
+    if let Some(ref disr) = variant.node.disr_expr {
+        visitor.visit_expr(disr);
+    }
+}
 
 pub trait Filter {
-    // Returns false if the item should be kept and true if it should be removed.
-    fn apply(&self, &Item) -> bool;
+    // Returns false if the node should be kept and true if it should be removed.
+    fn apply(&self, &Context, &Node) -> bool;
 }
 
 // A filter which represents the AND of all of its sub-filters.
@@ -16,9 +398,9 @@ struct AllFilter(Vec<Box<Filter>>);
 
 impl Filter for AllFilter {
     // Returns true only if all filters return true.
-    fn apply(&self, item: &Item) -> bool {
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
         for f in self.0.iter() {
-            if !f.apply(item) {
+            if !f.apply(ctx, node) {
                 return false;
             }
         }
@@ -31,9 +413,9 @@ struct AnyFilter(Vec<Box<Filter>>);
 
 impl Filter for AnyFilter {
     // Returns true if any filter returns true.
-    fn apply(&self, item: &Item) -> bool {
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
         for f in self.0.iter() {
-            if f.apply(item) {
+            if f.apply(ctx, node) {
                 return true;
             }
         }
@@ -41,28 +423,76 @@ impl Filter for AnyFilter {
     }
 }
 
+// A filter which represents the exclusive OR of all of its sub-filters.
+struct OneFilter(Vec<Box<Filter>>);
+
+impl Filter for OneFilter {
+    // Returns true if exactly one filter returns true.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        let mut matched = false;
+        for f in self.0.iter() {
+            if f.apply(ctx, node) {
+                if matched {
+                    return false;
+                }
+                matched = true;
+            }
+        }
+        matched
+    }
+}
+
+// A filter which uses one sub-filter to select which of two other sub-filters to apply.
+struct CondFilter {
+    cond: Box<Filter>,
+    then: Box<Filter>,
+    els: Box<Filter>,
+}
+
+impl Filter for CondFilter {
+    // Returns whatever the `then` filter returns if the condition returns true, and whatever the
+    // `els` filter returns otherwise.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        if self.cond.apply(ctx, node) {
+            self.then.apply(ctx, node)
+        } else {
+            self.els.apply(ctx, node)
+        }
+    }
+}
+
 // A filter which represents the negation of its sub-filter.
 struct NotFilter(Box<Filter>);
 
 impl Filter for NotFilter {
     // Returns the negation of whatever the wrapped filter returns.
-    fn apply(&self, item: &Item) -> bool {
-        !self.0.apply(item)
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        !self.0.apply(ctx, node)
+    }
+}
+
+// A filter which is shared between multiple references to a let()-bound name.
+struct SharedFilter(Rc<Box<Filter>>);
+
+impl Filter for SharedFilter {
+    // Returns whatever the shared filter returns.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        self.0.apply(ctx, node)
     }
 }
 
 // A filter which always returns true.
-struct AlwaysFilter;
+pub struct AlwaysFilter;
 
 impl AlwaysFilter {
-    fn new() -> Box<Filter> {
+    pub fn new() -> Box<Filter> {
         Box::new(AlwaysFilter {})
     }
 }
 
 impl Filter for AlwaysFilter {
     // Returns true.
-    fn apply(&self, _item: &Item) -> bool {
+    fn apply(&self, _ctx: &Context, _node: &Node) -> bool {
         true
     }
 }
@@ -78,7 +508,7 @@ impl NeverFilter {
 
 impl Filter for NeverFilter {
     // Returns false.
-    fn apply(&self, _item: &Item) -> bool {
+    fn apply(&self, _ctx: &Context, _node: &Node) -> bool {
         false
     }
 }
@@ -94,247 +524,2206 @@ impl RegexFilter {
 
 impl Filter for RegexFilter {
     // Returns true if the item's name matches the regex.
-    fn apply(&self, item: &Item) -> bool {
-        self.0.is_match(item.ident.name.as_str().as_ref())
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        self.0.is_match(node.ident().name.as_str().as_ref())
     }
 }
 
-// A filter which returns true if an item is decorated with `#[test]`.
-struct TestFilter;
+// A filter which returns true if an item's name starts with the specified prefix.
+struct PrefixFilter(String);
 
-impl TestFilter {
-    fn new() -> Box<Filter> {
-        Box::new(TestFilter {})
+impl PrefixFilter {
+    fn new(prefix: &str) -> Box<Filter> {
+        Box::new(PrefixFilter(String::from(prefix)))
     }
 }
 
-impl Filter for TestFilter {
-    // Returns true if item is decorated with `#[test]`.
-    fn apply(&self, item: &Item) -> bool {
-        println!("Is {} a test? {}",
-                 item.ident.name.as_str().as_ref() as &str,
-                 attr::contains_name(&item.attrs, "test"));
-        attr::contains_name(&item.attrs, "test")
+impl Filter for PrefixFilter {
+    // Returns true if the item's name starts with the prefix.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        node.ident().name.as_str().starts_with(self.0.as_str())
     }
 }
 
-// A filter which returns true if an item is decorated with `#[bench]`.
-struct BenchFilter;
+// A filter which returns true if an item's name ends with the specified suffix.
+struct SuffixFilter(String);
 
-impl BenchFilter {
-    fn new() -> Box<Filter> {
-        Box::new(BenchFilter {})
+impl SuffixFilter {
+    fn new(suffix: &str) -> Box<Filter> {
+        Box::new(SuffixFilter(String::from(suffix)))
     }
 }
 
-impl Filter for BenchFilter {
-    // Returns true if item is decorated with `#[bench]`.
-    fn apply(&self, item: &Item) -> bool {
-        println!("Is {} a bench? {}",
-                 item.ident.name.as_str().as_ref() as &str,
-                 attr::contains_name(&item.attrs, "bench"));
-        attr::contains_name(&item.attrs, "bench")
+impl Filter for SuffixFilter {
+    // Returns true if the item's name ends with the suffix.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        node.ident().name.as_str().ends_with(self.0.as_str())
     }
 }
 
-// A filter which returns true if an item is a function declaration.
-struct FnFilter;
+// A filter which returns true if an item's fully qualified path matches the specified regex.
+struct PathFilter(Regex);
 
-impl FnFilter {
-    fn new() -> Box<Filter> {
-        Box::new(FnFilter {})
+impl PathFilter {
+    fn new(re: Regex) -> Box<Filter> {
+        Box::new(PathFilter(re))
     }
 }
 
-impl Filter for FnFilter {
-    // Returns true if item is a function declaration.
-    fn apply(&self, item: &Item) -> bool {
-        if let ItemKind::Fn(..) = item.node {
-            true
-        } else {
-            false
+impl Filter for PathFilter {
+    // Returns true if the item's fully qualified path matches the regex.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        self.0.is_match(ctx.node_path(node).as_str())
+    }
+}
+
+// Returns the concatenated text of an item's doc comments, one line per `#[doc = "..."]` attribute.
+// The comment markers of sugared doc comments (`///`, `//!`, `/** */`, etc.) are stripped.
+fn doc_text(attrs: &[Attribute]) -> String {
+    let mut lines = Vec::new();
+    for attr in attrs.iter() {
+        if !attr.check_name("doc") {
+            continue;
+        }
+        if let Some(value) = attr.value_str() {
+            if attr.is_sugared_doc {
+                lines.push(comments::strip_doc_comment_decoration(&value.as_str()));
+            } else {
+                lines.push(String::from(&value.as_str() as &str));
+            }
         }
     }
+    lines.join("\n")
 }
 
-// A filter which returns true if an item is the root module of a crate.
-struct RootModFilter;
+// A filter which returns true if an item's doc comments match the specified regex.
+struct DocFilter(Regex);
 
-impl RootModFilter {
+impl DocFilter {
+    fn new(re: Regex) -> Box<Filter> {
+        Box::new(DocFilter(re))
+    }
+}
+
+impl Filter for DocFilter {
+    // Returns true if the item's doc comments match the regex.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        self.0.is_match(doc_text(node.attrs()).as_str())
+    }
+}
+
+// A filter which returns true if an item has no doc comments.
+struct UndocumentedFilter;
+
+impl UndocumentedFilter {
     fn new() -> Box<Filter> {
-        Box::new(RootModFilter {})
+        Box::new(UndocumentedFilter {})
     }
 }
 
-impl Filter for RootModFilter {
-    // Returns true if item is the root module of a crate.
-    fn apply(&self, item: &Item) -> bool {
-        let &Item {
-                 ref ident,
-                 ref node,
-                 ..
-             } = item;
-        if let &ItemKind::Mod(ref md) = node {
-            ident.name.as_str() == ""
-        } else {
-            false
-        }
+impl Filter for UndocumentedFilter {
+    // Returns true if item has no doc comments (`///`, `/** */`, or `#[doc = "..."]`).
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        !attr::contains_name(node.attrs(), "doc")
     }
 }
 
-// Convenience function for constructing AllFilters.
-fn and(filters: Vec<Box<Filter>>) -> Box<Filter> {
-    let mut v = Vec::new();
-    v.extend(filters);
-    Box::new(AllFilter(v))
+// Renders a meta item in a canonical form with no whitespace (e.g., `any(unix,target_os="redox")`).
+fn render_meta(meta: &MetaItem) -> String {
+    let mut s = String::from(&meta.name().as_str() as &str);
+    match meta.node {
+        MetaItemKind::Word => {}
+        MetaItemKind::NameValue(ref lit) => {
+            s.push('=');
+            s.push_str(&pprust::lit_to_string(lit));
+        }
+        MetaItemKind::List(ref list) => {
+            let mut v = Vec::new();
+            for nested in list {
+                v.push(render_nested_meta(nested));
+            }
+            s.push('(');
+            s.push_str(&v.join(","));
+            s.push(')');
+        }
+    }
+    s
 }
 
-// Convenience function for constructing AnyFilters.
-fn or(filters: Vec<Box<Filter>>) -> Box<Filter> {
-    let mut v = Vec::new();
-    v.extend(filters);
-    Box::new(AnyFilter(v))
+// Renders a nested meta item (a meta item or a literal) in the same form as render_meta.
+fn render_nested_meta(nested: &NestedMetaItem) -> String {
+    if let Some(meta) = nested.meta_item() {
+        render_meta(meta)
+    } else if let Some(lit) = nested.literal() {
+        pprust::lit_to_string(lit)
+    } else {
+        String::new()
+    }
 }
 
-// Convenience function for constructing NotFilters.
-fn not(filter: Box<Filter>) -> Box<Filter> {
-    Box::new(NotFilter(filter))
+// Removes all whitespace which is not inside of a string literal so that a user-supplied cfg
+// predicate can be compared against the output of render_meta.
+fn normalize_meta(s: &str) -> String {
+    let mut out = String::new();
+    let mut in_quote = false;
+    let mut escaped = false;
+    for c in s.chars() {
+        if in_quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_quote = false;
+            }
+        } else if c == '"' {
+            in_quote = true;
+        } else if c.is_whitespace() {
+            continue;
+        }
+        out.push(c);
+    }
+    out
 }
 
-const ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_FILTER";
+// A filter which returns true if an item is gated behind a `#[cfg(...)]` whose predicate is or
+// contains the given predicate. For example, `cfg("unix")` matches both `#[cfg(unix)]` and
+// `#[cfg(any(unix, windows))]`.
+struct CfgFilter(String);
 
-pub fn env_to_filter() -> Box<Filter> {
-    match env::var(ENV_VAR_NAME) {
-        // Never filter out the root module
-        Ok(filter) => and(vec![not(RootModFilter::new()), parse_filter(filter)]),
-        Err(_) => Box::new(NeverFilter {}),
+impl CfgFilter {
+    fn new(predicate: &str) -> Box<Filter> {
+        Box::new(CfgFilter(normalize_meta(predicate)))
+    }
+
+    // Returns true if the nested meta item or any nested meta item inside of it matches.
+    fn matches(&self, nested: &NestedMetaItem) -> bool {
+        if render_nested_meta(nested) == self.0 {
+            return true;
+        }
+        if let Some(list) = nested.meta_item_list() {
+            for nested in list {
+                if self.matches(nested) {
+                    return true;
+                }
+            }
+        }
+        false
     }
 }
 
-fn parse_filter(filter: String) -> Box<Filter> {
-    // require that the top-level expression be a call
-    match call(filter.as_bytes()) {
-        IResult::Done(_, out) => {
-            println!("{:?}", out);
-            expr_to_filter(&Expr::Call(out))
+impl Filter for CfgFilter {
+    // Returns true if any of the item's cfg attributes contains the predicate.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        for attr in node.attrs().iter() {
+            if !attr.check_name("cfg") {
+                continue;
+            }
+            if let Some(list) = attr.meta_item_list() {
+                for nested in list.iter() {
+                    if self.matches(nested) {
+                        return true;
+                    }
+                }
+            }
         }
-        IResult::Error(err) => panic!("error parsing input: {:?}", err),
-        IResult::Incomplete(left) => panic!("unparsed input: {:?}", left),
+        false
     }
 }
 
-fn expr_to_filter(expr: &Expr) -> Box<Filter> {
-    match expr {
-        &Expr::Quote(ref s) => panic!("unexpected string argument"),
-        &Expr::Call(ref call) => {
-            match call.name.as_str() {
-                "test" => mk_no_arg_filter("test", &call.args, TestFilter::new()),
-                "bench" => mk_no_arg_filter("bench", &call.args, BenchFilter::new()),
-                "regex" => mk_regex_filter(&call.args),
-                "fn" => mk_no_arg_filter("fn", &call.args, FnFilter::new()),
-                "true" => mk_no_arg_filter("true", &call.args, AlwaysFilter::new()),
-                "false" => mk_no_arg_filter("false", &call.args, NeverFilter::new()),
-                "and" => mk_and_filter(&call.args),
-                "or" => mk_or_filter(&call.args),
-                "not" => mk_not_filter(&call.args),
-                s => panic!("unrecognized function: {}", s),
+// Returns true if any of the attributes named attr_name (e.g., `derive`) has a list containing an
+// element named name (e.g., `#[derive(Clone, Debug)]` contains `Debug`).
+fn list_attr_contains(attrs: &[Attribute], attr_name: &str, name: &str) -> bool {
+    for attr in attrs.iter() {
+        if !attr.check_name(attr_name) {
+            continue;
+        }
+        if let Some(list) = attr.meta_item_list() {
+            for nested in list.iter() {
+                if nested.check_name(name) {
+                    return true;
+                }
             }
         }
     }
+    false
 }
 
-fn mk_regex_filter(args: &Vec<Expr>) -> Box<Filter> {
-    if args.len() != 1 {
-        panic!("regex() takes 1 argument");
+// A filter which returns true if an item's `#[derive(...)]` list contains the given trait name.
+struct DeriveFilter(String);
+
+impl DeriveFilter {
+    fn new(name: &str) -> Box<Filter> {
+        Box::new(DeriveFilter(String::from(name)))
+    }
+}
+
+impl Filter for DeriveFilter {
+    // Returns true if item derives the given trait.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        list_attr_contains(node.attrs(), "derive", &self.0)
     }
-    if let Expr::Quote(ref s) = args[0] {
-        match Regex::new(s.as_str()) {
-            Ok(re) => RegexFilter::new(re),
-            Err(err) => panic!("regex(): could not parse argument: {}", err),
+}
+
+// Translates a glob into an equivalent anchored regex. `?` matches any single character and `*`
+// matches any sequence of characters. If a separator is given, neither matches the separator, and
+// `**` matches any sequence of characters including separators (`**/` also matches nothing, so
+// `src/**/*.rs` matches `src/lib.rs`).
+fn glob_to_regex(glob: &str, separator: Option<char>) -> Regex {
+    let mut re = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, separator) {
+            ('*', Some(sep)) => {
+                let escaped = regex::escape(&sep.to_string());
+                if chars.peek() != Some(&'*') {
+                    re.push_str(&format!("[^{}]*", escaped));
+                    continue;
+                }
+                chars.next();
+                if chars.peek() == Some(&sep) {
+                    chars.next();
+                    re.push_str(&format!("(?:.*{})?", escaped));
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            ('*', None) => re.push_str(".*"),
+            ('?', Some(sep)) => re.push_str(&format!("[^{}]", regex::escape(&sep.to_string()))),
+            ('?', None) => re.push('.'),
+            (c, _) => re.push_str(&regex::escape(&c.to_string())),
         }
-    } else {
-        panic!("regex() only takes a string argument")
     }
+    re.push('$');
+    Regex::new(&re).unwrap()
 }
 
-fn mk_no_arg_filter(name: &str, args: &Vec<Expr>, filter: Box<Filter>) -> Box<Filter> {
-    if args.len() != 0 {
-        panic!("{}() takes no arguments", name);
+// A filter which returns true if an item was defined in a source file matching the specified glob
+// (e.g., `src/generated/**`).
+struct FileFilter(Regex);
+
+impl FileFilter {
+    fn new(glob: &str) -> Box<Filter> {
+        Box::new(FileFilter(glob_to_regex(glob, Some('/'))))
     }
-    filter
 }
 
-fn mk_and_filter(args: &Vec<Expr>) -> Box<Filter> {
-    if args.len() == 0 {
-        panic!("and() takes 1 or more arguments");
+impl Filter for FileFilter {
+    // Returns true if the item's source file matches the glob.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        self.0.is_match(&ctx.codemap.span_to_filename(node.span()))
     }
-    and(args_to_filters(args))
 }
 
-fn mk_or_filter(args: &Vec<Expr>) -> Box<Filter> {
-    if args.len() == 0 {
-        panic!("or() takes 1 or more arguments");
+// A filter which returns true if an item is defined entirely within the given (1-based, inclusive)
+// range of lines of its source file.
+struct LinesFilter {
+    start: usize,
+    end: usize,
+}
+
+impl LinesFilter {
+    fn new(start: usize, end: usize) -> Box<Filter> {
+        Box::new(LinesFilter {
+                     start: start,
+                     end: end,
+                 })
     }
-    or(args_to_filters(args))
 }
 
-fn mk_not_filter(args: &Vec<Expr>) -> Box<Filter> {
-    if args.len() != 1 {
-        panic!("not() takes 1 argument");
+impl Filter for LinesFilter {
+    // Returns true if the item's first and last lines are both within the range.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        let span = node.span();
+        let lo = ctx.codemap.lookup_char_pos(span.lo).line;
+        let hi = ctx.codemap.lookup_char_pos(span.hi).line;
+        self.start <= lo && hi <= self.end
     }
-    not(expr_to_filter(&args[0]))
 }
 
-fn args_to_filters(args: &Vec<Expr>) -> Vec<Box<Filter>> {
-    let mut v = Vec::new();
-    for arg in args {
-        v.push(expr_to_filter(arg));
-    }
-    v
-}
-
-#[derive(Debug)]
-enum Expr {
-    Quote(String),
-    Call(Call),
-}
-
-#[derive(Debug)]
-struct Call {
-    name: String,
-    args: Vec<Expr>,
-}
-
-fn bytes_to_string(bytes: &[u8]) -> String {
-    String::from_utf8_lossy(bytes).into_owned()
-}
-
-// match a quoted string (a quote followed by non-quote characters followed by a quote)
-named!(quote<String>, do_parse!(
-    quote_: delimited!(char!('"'), take_until!("\""), char!('"')) >>
-    (bytes_to_string(quote_))
-));
-// match a name (an alphabetic sequence)
-// NOTE: The '^' at the beginning is VERY IMPORTANT - without it, we'd just consume and throw away
-// any non-matching sequence of bytes until we found a match.
-named!(name<String>, do_parse!(
-    name_: re_bytes_find!("^[a-z]+") >>
-    (bytes_to_string(name_))
-));
-// match an argument list (comma-separated expressions surrounded by parentheses)
-named!(args<Vec<Expr> >, delimited!(
-    ws!(char!('(')),
-    separated_list!(ws!(char!(',')), expr),
-    ws!(char!(')'))
-));
-// match a call (a name followed by an argument list)
-named!(call<Call>, do_parse!(
-    name_: name >>
-    args_: args >>
-    (Call{name: name_, args: args_})
-));
-// match an expression (either a call or a quote)
-named!(expr<Expr>, alt_complete!(
-    do_parse!(call_: ws!(call) >> (Expr::Call(call_))) |
-    do_parse!(quote_: ws!(quote) >> (Expr::Quote(quote_)))
-));
+// A filter which returns true if an item's `#[allow(...)]` attributes allow the given lint.
+struct AllowFilter(String);
+
+impl AllowFilter {
+    fn new(lint: &str) -> Box<Filter> {
+        Box::new(AllowFilter(String::from(lint)))
+    }
+}
+
+impl Filter for AllowFilter {
+    // Returns true if item is decorated with `#[allow(...)]` for the given lint.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        list_attr_contains(node.attrs(), "allow", &self.0)
+    }
+}
+
+// A filter which returns true if an item's `#[repr(...)]` attributes contain the given
+// representation (e.g., `C`, `transparent`, or `u8`).
+struct ReprFilter(String);
+
+impl ReprFilter {
+    fn new(repr: &str) -> Box<Filter> {
+        Box::new(ReprFilter(String::from(repr)))
+    }
+}
+
+impl Filter for ReprFilter {
+    // Returns true if item is decorated with `#[repr(...)]` containing the given representation.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        list_attr_contains(node.attrs(), "repr", &self.0)
+    }
+}
+
+// A filter which returns true if an item is nested inside of at least the given number of modules
+// (not counting the root module).
+struct DepthFilter(usize);
+
+impl DepthFilter {
+    fn new(depth: usize) -> Box<Filter> {
+        Box::new(DepthFilter(depth))
+    }
+}
+
+impl Filter for DepthFilter {
+    // Returns true if item is nested at least the given number of modules deep.
+    fn apply(&self, ctx: &Context, _node: &Node) -> bool {
+        ctx.path.len() >= self.0
+    }
+}
+
+// A filter which returns true if an item lives directly inside of a module with the given name.
+struct ParentFilter(String);
+
+impl ParentFilter {
+    fn new(name: &str) -> Box<Filter> {
+        Box::new(ParentFilter(String::from(name)))
+    }
+}
+
+impl Filter for ParentFilter {
+    // Returns true if the item's enclosing module has the given name. Items in the root module
+    // have no enclosing module name, and never match.
+    fn apply(&self, ctx: &Context, _node: &Node) -> bool {
+        ctx.path.last() == Some(&self.0)
+    }
+}
+
+// A filter which returns true if an item's source spans more than the given number of lines.
+struct LargerThanFilter(usize);
+
+impl LargerThanFilter {
+    fn new(lines: usize) -> Box<Filter> {
+        Box::new(LargerThanFilter(lines))
+    }
+}
+
+impl Filter for LargerThanFilter {
+    // Returns true if the item covers more than the given number of lines, counting both its
+    // first and last lines.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        let span = node.span();
+        let lo = ctx.codemap.lookup_char_pos(span.lo).line;
+        let hi = ctx.codemap.lookup_char_pos(span.hi).line;
+        hi - lo + 1 > self.0
+    }
+}
+
+// A filter which returns true if an item is decorated with `#[test]`.
+struct TestFilter;
+
+impl TestFilter {
+    fn new() -> Box<Filter> {
+        Box::new(TestFilter {})
+    }
+}
+
+impl Filter for TestFilter {
+    // Returns true if item is decorated with `#[test]`.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        attr::contains_name(node.attrs(), "test")
+    }
+}
+
+// A filter which returns true if an item is decorated with `#[bench]`.
+struct BenchFilter;
+
+impl BenchFilter {
+    fn new() -> Box<Filter> {
+        Box::new(BenchFilter {})
+    }
+}
+
+impl Filter for BenchFilter {
+    // Returns true if item is decorated with `#[bench]`.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        attr::contains_name(node.attrs(), "bench")
+    }
+}
+
+// A filter which returns true if an item's symbol name is fixed by `#[no_mangle]` or
+// `#[export_name = "..."]`.
+struct NoMangleFilter;
+
+impl NoMangleFilter {
+    fn new() -> Box<Filter> {
+        Box::new(NoMangleFilter {})
+    }
+}
+
+impl Filter for NoMangleFilter {
+    // Returns true if item is decorated with `#[no_mangle]` or `#[export_name]`.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        attr::contains_name(node.attrs(), "no_mangle") ||
+        attr::contains_name(node.attrs(), "export_name")
+    }
+}
+
+// A filter which returns true if an item is a conventional unit test module: a module named
+// `tests` which is decorated with `#[cfg(test)]`.
+struct TestModFilter;
+
+impl TestModFilter {
+    fn new() -> Box<Filter> {
+        Box::new(TestModFilter {})
+    }
+}
+
+impl Filter for TestModFilter {
+    // Returns true if item is a module named `tests` which is decorated with `#[cfg(test)]`.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        if let &Node::Item(&Item { ref ident, node: ItemKind::Mod(..), ref attrs, .. }) = node {
+            ident.name.as_str() == "tests" && list_attr_contains(attrs, "cfg", "test")
+        } else {
+            false
+        }
+    }
+}
+
+// A filter which returns true if an item is tagged with the given tag by a
+// `#[disable_code::tag("...")]` attribute.
+struct TagFilter(String);
+
+impl TagFilter {
+    fn new(tag: &str) -> Box<Filter> {
+        Box::new(TagFilter(String::from(tag)))
+    }
+}
+
+impl Filter for TagFilter {
+    // Returns true if item is tagged with the given tag.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        attrs::str_args(node.attrs(), "tag").contains(&self.0)
+    }
+}
+
+// A filter which returns true if an item is decorated with an attribute of the given name.
+struct AttrFilter(String);
+
+impl AttrFilter {
+    fn new(name: &str) -> Box<Filter> {
+        Box::new(AttrFilter(String::from(name)))
+    }
+}
+
+impl Filter for AttrFilter {
+    // Returns true if item is decorated with an attribute of the given name.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        attr::contains_name(node.attrs(), self.0.as_str())
+    }
+}
+
+// A filter which returns true if an item carries a `key = "value"` meta item, either as a
+// top-level attribute (`#[key = "value"]`) or nested inside another attribute's list (e.g.
+// `#[cfg(feature = "gui")]` matches key `feature` and value `gui`).
+struct AttrValueFilter {
+    key: String,
+    value: String,
+}
+
+impl AttrValueFilter {
+    fn new(key: &str, value: &str) -> Box<Filter> {
+        Box::new(AttrValueFilter {
+                     key: String::from(key),
+                     value: String::from(value),
+                 })
+    }
+
+    // Returns true if the meta item or any meta item nested inside of it matches.
+    fn matches(&self, meta: &MetaItem) -> bool {
+        if meta.name().as_str() == self.key.as_str() {
+            if let Some(value) = meta.value_str() {
+                if value.as_str() == self.value.as_str() {
+                    return true;
+                }
+            }
+        }
+        if let Some(list) = meta.meta_item_list() {
+            for nested in list {
+                if let Some(meta) = nested.meta_item() {
+                    if self.matches(meta) {
+                        return true;
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+impl Filter for AttrValueFilter {
+    // Returns true if any of the item's attributes contains a matching meta item.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        for attr in node.attrs().iter() {
+            if let Some(meta) = attr.meta() {
+                if self.matches(&meta) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+// A filter which returns true if an item is gated behind a `#[cfg(...)]` whose predicate is or
+// contains `feature = "name"`. Unlike cfg(), the name is compared against the parsed meta item, so
+// it needs no escaping.
+struct FeatureFilter(AttrValueFilter);
+
+impl FeatureFilter {
+    fn new(name: &str) -> Box<Filter> {
+        Box::new(FeatureFilter(AttrValueFilter {
+                                   key: String::from("feature"),
+                                   value: String::from(name),
+                               }))
+    }
+}
+
+impl Filter for FeatureFilter {
+    // Returns true if any of the item's cfg attributes contains the feature.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        for attr in node.attrs().iter() {
+            if !attr.check_name("cfg") {
+                continue;
+            }
+            if let Some(meta) = attr.meta() {
+                if self.0.matches(&meta) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+// A filter which returns true if an item is a function declaration.
+struct FnFilter;
+
+impl FnFilter {
+    fn new() -> Box<Filter> {
+        Box::new(FnFilter {})
+    }
+}
+
+impl Filter for FnFilter {
+    // Returns true if item is a function declaration.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        match node {
+            &Node::Item(&Item { node: ItemKind::Fn(..), .. }) |
+            &Node::ForeignItem(&ForeignItem { node: ForeignItemKind::Fn(..), .. }) |
+            &Node::ImplItem(&ImplItem { node: ImplItemKind::Method(..), .. }) |
+            &Node::TraitItem(&TraitItem { node: TraitItemKind::Method(..), .. }) => true,
+            _ => false,
+        }
+    }
+}
+
+// The names accepted by kind(): those of the item kinds, in the same order as the variants of
+// ItemKind, followed by that of enum variants.
+const KINDS: &[&str] = &["extern_crate",
+                         "use",
+                         "static",
+                         "const",
+                         "fn",
+                         "mod",
+                         "extern",
+                         "global_asm",
+                         "type",
+                         "enum",
+                         "struct",
+                         "union",
+                         "trait",
+                         "impl",
+                         "macro",
+                         "macro_rules",
+                         "variant"];
+
+// Returns the kind() name of an item kind. Default impls (`impl Trait for .. {}`) are treated as
+// impls.
+fn kind_name(kind: &ItemKind) -> &'static str {
+    match kind {
+        &ItemKind::ExternCrate(..) => "extern_crate",
+        &ItemKind::Use(..) => "use",
+        &ItemKind::Static(..) => "static",
+        &ItemKind::Const(..) => "const",
+        &ItemKind::Fn(..) => "fn",
+        &ItemKind::Mod(..) => "mod",
+        &ItemKind::ForeignMod(..) => "extern",
+        &ItemKind::GlobalAsm(..) => "global_asm",
+        &ItemKind::Ty(..) => "type",
+        &ItemKind::Enum(..) => "enum",
+        &ItemKind::Struct(..) => "struct",
+        &ItemKind::Union(..) => "union",
+        &ItemKind::Trait(..) => "trait",
+        &ItemKind::DefaultImpl(..) => "impl",
+        &ItemKind::Impl(..) => "impl",
+        &ItemKind::Mac(..) => "macro",
+        &ItemKind::MacroDef(..) => "macro_rules",
+    }
+}
+
+// A filter which returns true if an item is of the given kind (one of KINDS).
+struct KindFilter(&'static str);
+
+impl KindFilter {
+    fn new(kind: &'static str) -> Box<Filter> {
+        Box::new(KindFilter(kind))
+    }
+}
+
+impl Filter for KindFilter {
+    // Returns true if item is of the given kind.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        node.kind_name() == self.0
+    }
+}
+
+// The names accepted by vis(). Each names a distinct visibility; `pub` only matches unrestricted
+// `pub` items, `priv` matches items with no visibility qualifier (or `pub(self)`), and `pub(in)`
+// matches items restricted to any other path (`pub(in some::path)`).
+const VISIBILITIES: &[&str] = &["pub", "pub(crate)", "pub(super)", "pub(in)", "priv"];
+
+// The expressions which can be selected by name with preset() or RUST_DISABLE_CODE_PRESET.
+// strip_private leaves out the protected nodes which are usually private, since it isn't meant to
+// remove them.
+const PRESETS: &[(&str, &str)] = &[("strip_tests", "or(test(), test_mod())"),
+                                   ("strip_benches", "bench()"),
+                                   ("strip_private",
+                                    "and(vis(\"priv\"), not(main()), not(no_mangle()))"),
+                                   ("strip_debug",
+                                    "or(cfg(\"debug_assertions\"), regex(\"^(dbg|debug)_\"))")];
+
+// Returns the vis() name of a visibility.
+fn vis_name(vis: &Visibility) -> &'static str {
+    match vis {
+        &Visibility::Public => "pub",
+        &Visibility::Crate(..) => "pub(crate)",
+        &Visibility::Restricted { ref path, .. } => {
+            if path.segments.len() != 1 {
+                return "pub(in)";
+            }
+            match path.segments[0].identifier.name.as_str().as_ref() as &str {
+                "super" => "pub(super)",
+                "self" => "priv",
+                _ => "pub(in)",
+            }
+        }
+        &Visibility::Inherited => "priv",
+    }
+}
+
+// A filter which returns true if an item has the given visibility (one of VISIBILITIES).
+struct VisFilter(&'static str);
+
+impl VisFilter {
+    fn new(vis: &'static str) -> Box<Filter> {
+        Box::new(VisFilter(vis))
+    }
+}
+
+impl Filter for VisFilter {
+    // Returns true if item has the given visibility.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        ctx.vis(node).map_or(false, |vis| vis_name(vis) == self.0)
+    }
+}
+
+// Returns the generics of an item, or None if its kind cannot have generic parameters.
+fn item_generics(kind: &ItemKind) -> Option<&Generics> {
+    match kind {
+        &ItemKind::Fn(_, _, _, _, ref generics, _) |
+        &ItemKind::Ty(_, ref generics) |
+        &ItemKind::Enum(_, ref generics) |
+        &ItemKind::Struct(_, ref generics) |
+        &ItemKind::Union(_, ref generics) |
+        &ItemKind::Trait(_, ref generics, _, _) |
+        &ItemKind::Impl(_, _, _, ref generics, _, _, _) => Some(generics),
+        _ => None,
+    }
+}
+
+// A filter which returns true if an item has at least one type or lifetime parameter.
+struct GenericFilter;
+
+impl GenericFilter {
+    fn new() -> Box<Filter> {
+        Box::new(GenericFilter {})
+    }
+}
+
+impl Filter for GenericFilter {
+    // Returns true if item has at least one type or lifetime parameter.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        match node.generics() {
+            Some(generics) => !generics.lifetimes.is_empty() || !generics.ty_params.is_empty(),
+            None => false,
+        }
+    }
+}
+
+// A filter which returns true if an item is a function which takes the given number of arguments.
+struct ArityFilter(usize);
+
+impl ArityFilter {
+    fn new(arity: usize) -> Box<Filter> {
+        Box::new(ArityFilter(arity))
+    }
+}
+
+impl Filter for ArityFilter {
+    // Returns true if item is a function which takes the given number of arguments. `self`
+    // counts as an argument.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        match node.fn_decl() {
+            Some(decl) => decl.inputs.len() == self.0,
+            None => false,
+        }
+    }
+}
+
+// A filter which returns true if an item is a function whose return type matches the specified
+// regex.
+struct ReturnsFilter(Regex);
+
+impl ReturnsFilter {
+    fn new(re: Regex) -> Box<Filter> {
+        Box::new(ReturnsFilter(re))
+    }
+}
+
+impl Filter for ReturnsFilter {
+    // Returns true if item is a function whose pretty-printed return type matches the regex.
+    // Functions with no explicit return type are treated as returning `()`.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        let decl = match node.fn_decl() {
+            Some(decl) => decl,
+            None => return false,
+        };
+        match decl.output {
+            FunctionRetTy::Default(..) => self.0.is_match("()"),
+            FunctionRetTy::Ty(ref ty) => self.0.is_match(&pprust::ty_to_string(ty)),
+        }
+    }
+}
+
+// A filter which returns true if an item is a function whose body matches the specified regex.
+struct BodyContainsFilter(Regex);
+
+impl BodyContainsFilter {
+    fn new(re: Regex) -> Box<Filter> {
+        Box::new(BodyContainsFilter(re))
+    }
+}
+
+impl Filter for BodyContainsFilter {
+    // Returns true if item is a function whose body's source text matches the regex. If the
+    // source text is unavailable, the pretty-printed body is used instead.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        let body = match node.fn_body() {
+            Some(body) => body,
+            None => return false,
+        };
+        let text = match ctx.codemap.span_to_snippet(body.span) {
+            Ok(text) => text,
+            Err(_) => pprust::block_to_string(body),
+        };
+        self.0.is_match(&text)
+    }
+}
+
+// Searches an AST for calls to a path.
+struct CallFinder<'a> {
+    // The segments of the path to search for.
+    target: &'a [String],
+    found: bool,
+}
+
+impl<'a, 'ast> Visitor<'ast> for CallFinder<'a> {
+    fn visit_expr(&mut self, expr: &'ast AstExpr) {
+        if let ExprKind::Call(ref func, _) = expr.node {
+            if let ExprKind::Path(_, ref path) = func.node {
+                let segments: Vec<String> = path.segments
+                    .iter()
+                    .map(|s| String::from(&s.identifier.name.as_str() as &str))
+                    .collect();
+                if call_matches(self.target, &segments) {
+                    self.found = true;
+                }
+            }
+        }
+        visit::walk_expr(self, expr);
+    }
+
+    // Macro invocations have not been expanded yet, so there are no expressions inside of them to
+    // search.
+    fn visit_mac(&mut self, _mac: &'ast Mac) {}
+}
+
+// Strips the leading segments of a path which only say where it is relative to (`self`, `super`,
+// `crate`, and the empty segment of a path starting with `::`).
+fn strip_path_prefix(segments: &[String]) -> &[String] {
+    let relative = ["", "{{root}}", "self", "super", "crate"];
+    let start = segments.iter().take_while(|s| relative.contains(&s.as_str())).count();
+    &segments[start..]
+}
+
+// Returns true if a callee path names the target path. Since the callee may have been imported
+// under a shorter path, it matches if it is a suffix of the target, but it must include at least
+// the last two segments of the target (so `Command::new` matches `std::process::Command::new`,
+// but a bare `new` does not).
+fn call_matches(target: &[String], callee: &[String]) -> bool {
+    let target = strip_path_prefix(target);
+    let callee = strip_path_prefix(callee);
+    !callee.is_empty() && callee.len() >= cmp::min(2, target.len()) && target.ends_with(callee)
+}
+
+// A filter which returns true if an item contains a call to the specified path.
+struct CallsFilter(Vec<String>);
+
+impl CallsFilter {
+    fn new(path: &str) -> Box<Filter> {
+        Box::new(CallsFilter(path.split("::").map(String::from).collect()))
+    }
+}
+
+impl Filter for CallsFilter {
+    // Returns true if item contains a call expression whose callee is the path (see call_matches).
+    // Method calls are not matched.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        let mut finder = CallFinder {
+            target: &self.0,
+            found: false,
+        };
+        match node {
+            &Node::Item(item) => finder.visit_item(item),
+            &Node::ForeignItem(item) => finder.visit_foreign_item(item),
+            &Node::ImplItem(item) => finder.visit_impl_item(item),
+            &Node::TraitItem(item) => finder.visit_trait_item(item),
+            &Node::Variant(variant) => walk_variant(&mut finder, variant),
+        }
+        finder.found
+    }
+}
+
+// A filter which returns true if an item is a module containing more than the given number of
+// items.
+struct ModuleItemsOverFilter(usize);
+
+impl ModuleItemsOverFilter {
+    fn new(n: usize) -> Box<Filter> {
+        Box::new(ModuleItemsOverFilter(n))
+    }
+}
+
+impl Filter for ModuleItemsOverFilter {
+    // Returns true if item is a module with more than the given number of direct child items.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        if let &Node::Item(&Item { node: ItemKind::Mod(ref md), .. }) = node {
+            md.items.len() > self.0
+        } else {
+            false
+        }
+    }
+}
+
+// A filter which returns true if an item is an `unsafe fn` declaration.
+struct UnsafeFnFilter;
+
+impl UnsafeFnFilter {
+    fn new() -> Box<Filter> {
+        Box::new(UnsafeFnFilter {})
+    }
+}
+
+impl Filter for UnsafeFnFilter {
+    // Returns true if item is an `unsafe fn` declaration, including unsafe methods. Functions in
+    // `extern` blocks are always unsafe to call, so they match as well.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        match node {
+            &Node::Item(&Item { node: ItemKind::Fn(_, Unsafety::Unsafe, ..), .. }) |
+            &Node::ForeignItem(&ForeignItem { node: ForeignItemKind::Fn(..), .. }) => true,
+            &Node::ImplItem(&ImplItem { node: ImplItemKind::Method(ref sig, _), .. }) |
+            &Node::TraitItem(&TraitItem { node: TraitItemKind::Method(ref sig, _), .. }) => {
+                sig.unsafety == Unsafety::Unsafe
+            }
+            _ => false,
+        }
+    }
+}
+
+// A filter which returns true if an item is a `macro_rules!` macro definition.
+struct MacroDefFilter;
+
+impl MacroDefFilter {
+    fn new() -> Box<Filter> {
+        Box::new(MacroDefFilter {})
+    }
+}
+
+impl Filter for MacroDefFilter {
+    // Returns true if item is a macro definition. Depending on whether the item has been parsed
+    // into a macro definition yet, it is either an `ItemKind::MacroDef` or an invocation of
+    // `macro_rules!`.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        match node {
+            &Node::Item(&Item { node: ItemKind::MacroDef(..), .. }) => true,
+            &Node::Item(&Item { node: ItemKind::Mac(ref mac), .. }) => {
+                let segments = &mac.node.path.segments;
+                segments.len() == 1 && segments[0].identifier.name.as_str() == "macro_rules"
+            }
+            _ => false,
+        }
+    }
+}
+
+// A filter which returns true if an item is a trait impl whose trait path matches the specified
+// regex.
+struct ImplOfFilter(Regex);
+
+impl ImplOfFilter {
+    fn new(re: Regex) -> Box<Filter> {
+        Box::new(ImplOfFilter(re))
+    }
+}
+
+impl Filter for ImplOfFilter {
+    // Returns true if item is an `impl Trait for Type` block whose trait path matches the regex.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        match node {
+            &Node::Item(&Item { node: ItemKind::Impl(_, _, _, _, Some(ref trait_ref), _, _), .. }) |
+            &Node::Item(&Item { node: ItemKind::DefaultImpl(_, ref trait_ref), .. }) => {
+                self.0.is_match(pprust::path_to_string(&trait_ref.path).as_str())
+            }
+            _ => false,
+        }
+    }
+}
+
+// A filter which returns true if an item is an impl block whose self type matches the specified
+// regex.
+struct ImplForFilter(Regex);
+
+impl ImplForFilter {
+    fn new(re: Regex) -> Box<Filter> {
+        Box::new(ImplForFilter(re))
+    }
+}
+
+impl Filter for ImplForFilter {
+    // Returns true if item is an inherent or trait impl whose self type matches the regex.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        if let &Node::Item(&Item { node: ItemKind::Impl(_, _, _, _, _, ref ty, _), .. }) = node {
+            self.0.is_match(pprust::ty_to_string(ty).as_str())
+        } else {
+            false
+        }
+    }
+}
+
+// A filter which returns true if an item is reachable from outside of the crate through `pub`
+// items and `pub use` re-exports (not merely declared `pub` inside of a private module).
+struct ExportedFilter;
+
+impl ExportedFilter {
+    fn new() -> Box<Filter> {
+        Box::new(ExportedFilter {})
+    }
+}
+
+impl Filter for ExportedFilter {
+    // Returns true if item is reachable from outside of the crate.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        ctx.exported.contains(&ctx.node_path(node))
+    }
+}
+
+// A filter which returns true if the crate being compiled has the given name.
+struct CrateFilter(String);
+
+impl CrateFilter {
+    fn new(name: &str) -> Box<Filter> {
+        // Cargo package names may contain hyphens, but crate names use underscores instead.
+        Box::new(CrateFilter(name.replace("-", "_")))
+    }
+}
+
+impl Filter for CrateFilter {
+    // Returns true if the crate being compiled has the given name.
+    fn apply(&self, ctx: &Context, _node: &Node) -> bool {
+        ctx.crate_name == self.0
+    }
+}
+
+// A filter which returns true if the compilation target has the given property (e.g., key `os`
+// and value `linux` for `target_os = "linux"`).
+struct TargetFilter {
+    key: String,
+    value: String,
+}
+
+impl TargetFilter {
+    fn new(key: &str, value: &str) -> Box<Filter> {
+        Box::new(TargetFilter {
+                     key: format!("target_{}", key),
+                     value: String::from(value),
+                 })
+    }
+}
+
+impl Filter for TargetFilter {
+    // Returns true if the session's cfg options contain `target_<key> = "<value>"`.
+    fn apply(&self, ctx: &Context, _node: &Node) -> bool {
+        ctx.config.iter().any(|&(name, value)| {
+            name.as_str() == self.key.as_str() &&
+            value.map_or(false, |value| value.as_str() == self.value.as_str())
+        })
+    }
+}
+
+// A filter which returns true if a node is a declaration inside of an `extern` block.
+struct ForeignFilter;
+
+impl ForeignFilter {
+    fn new() -> Box<Filter> {
+        Box::new(ForeignFilter {})
+    }
+}
+
+impl Filter for ForeignFilter {
+    // Returns true if node is a declaration inside of an `extern` block.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        if let &Node::ForeignItem(..) = node {
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// A filter which returns true if an item is the crate's entry point: a top-level function named
+// `main`, or a function decorated with `#[main]` or `#[start]`.
+struct MainFilter;
+
+impl MainFilter {
+    fn new() -> Box<Filter> {
+        Box::new(MainFilter {})
+    }
+}
+
+impl Filter for MainFilter {
+    // Returns true if item is the crate's entry point.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        if let &Node::Item(&Item { ref ident, node: ItemKind::Fn(..), .. }) = node {
+            (ctx.scopes.is_empty() && ident.name.as_str() == "main") || is_marked_entry_point(node)
+        } else {
+            false
+        }
+    }
+}
+
+// Returns true if the node is a function marked as the crate's entry point with `#[main]` or
+// `#[start]`.
+fn is_marked_entry_point(node: &Node) -> bool {
+    if let &Node::Item(&Item { node: ItemKind::Fn(..), ref attrs, .. }) = node {
+        attr::contains_name(attrs, "main") || attr::contains_name(attrs, "start")
+    } else {
+        false
+    }
+}
+
+// The protection() reason for the entry point, which allow_remove_main() also lifts.
+const ENTRY_POINT: &str = "the crate's entry point";
+
+// Returns why the node must not be removed, or None if it may be: the crate's entry point, the
+// panic handler, and items exported under unmangled symbol names, which other code may link to.
+fn protection(ctx: &Context, node: &Node) -> Option<&'static str> {
+    if MainFilter.apply(ctx, node) {
+        Some(ENTRY_POINT)
+    } else {
+        nested_protection(node)
+    }
+}
+
+// Returns why a node nested inside of an item must not be removed, like protection (such a node
+// is never in the root module, so it's only the entry point if it's marked as one).
+fn nested_protection(node: &Node) -> Option<&'static str> {
+    if is_marked_entry_point(node) {
+        Some(ENTRY_POINT)
+    } else if attr::contains_name(node.attrs(), "no_mangle") ||
+              attr::contains_name(node.attrs(), "export_name") {
+        Some("exported under an unmangled symbol name")
+    } else if attr::contains_name(node.attrs(), "panic_handler") {
+        Some("the panic handler")
+    } else {
+        None
+    }
+}
+
+// Finds the first protected node nested inside of another one, skipping the entry point if
+// allow_main is true. Macro invocations haven't been expanded, so they're not searched.
+struct ProtectedFinder {
+    allow_main: bool,
+    // A description of the protected node, its span, and why it's protected.
+    found: Option<(String, Span, &'static str)>,
+}
+
+impl ProtectedFinder {
+    fn check(&mut self, node: &Node) {
+        if self.found.is_some() {
+            return;
+        }
+        match nested_protection(node) {
+            Some(ENTRY_POINT) if self.allow_main => {}
+            Some(reason) => {
+                let description = format!("{} `{}`", node.kind_name(), node.ident());
+                self.found = Some((description, node.span(), reason));
+            }
+            None => {}
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for ProtectedFinder {
+    fn visit_item(&mut self, item: &'a Item) {
+        self.check(&Node::Item(item));
+        visit::walk_item(self, item);
+    }
+
+    fn visit_foreign_item(&mut self, item: &'a ForeignItem) {
+        self.check(&Node::ForeignItem(item));
+        visit::walk_foreign_item(self, item);
+    }
+
+    fn visit_impl_item(&mut self, item: &'a ImplItem) {
+        self.check(&Node::ImplItem(item));
+        visit::walk_impl_item(self, item);
+    }
+
+    fn visit_trait_item(&mut self, item: &'a TraitItem) {
+        self.check(&Node::TraitItem(item));
+        visit::walk_trait_item(self, item);
+    }
+
+    fn visit_mac(&mut self, _mac: &'a Mac) {}
+}
+
+// A filter which matches whatever its sub-filter matches, other than protected nodes and nodes
+// containing them, which are recorded as refusals instead, unless an unsafe_allow_exports()
+// wrapper inside of the sub-filter matched them (or, for the entry point, allow_remove_main()
+// appears in it).
+struct GuardFilter {
+    filter: Box<Filter>,
+    // The sub-filter's expression, in normalized form.
+    expr: String,
+    origin: String,
+    allow_main: bool,
+}
+
+impl GuardFilter {
+    // Returns why the node may not be removed, along with the protected node inside of it, if
+    // that's why.
+    fn protection(&self,
+                  ctx: &Context,
+                  node: &Node)
+                  -> Option<(&'static str, Option<(String, Span)>)> {
+        match protection(ctx, node) {
+            Some(ENTRY_POINT) if self.allow_main => {}
+            Some(reason) => return Some((reason, None)),
+            None => {}
+        }
+        let mut finder = ProtectedFinder {
+            allow_main: self.allow_main,
+            found: None,
+        };
+        match node {
+            &Node::Item(item) => visit::walk_item(&mut finder, item),
+            &Node::ImplItem(item) => visit::walk_impl_item(&mut finder, item),
+            &Node::TraitItem(item) => visit::walk_trait_item(&mut finder, item),
+            &Node::ForeignItem(_) |
+            &Node::Variant(_) => {}
+        }
+        finder.found.map(|(description, span, reason)| (reason, Some((description, span))))
+    }
+}
+
+impl Filter for GuardFilter {
+    // Returns true if the sub-filter does, unless the node is protected and may not be removed.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        // a GuardFilter may be applied inside of another one, through `inherited`
+        let outer = ctx.exports_allowed.replace(false);
+        let matched = self.filter.apply(ctx, node);
+        let allowed = matched && ctx.exports_allowed.get();
+        ctx.exports_allowed.set(outer || allowed);
+        if !matched || allowed {
+            return matched;
+        }
+        match self.protection(ctx, node) {
+            Some((reason, contains)) => {
+                ctx.refusals.borrow_mut().push(Refusal {
+                                                   path: ctx.node_path(node),
+                                                   kind: node.kind_name(),
+                                                   span: node.span(),
+                                                   reason: reason,
+                                                   contains: contains,
+                                                   filter: self.expr.clone(),
+                                                   origin: self.origin.clone(),
+                                               });
+                false
+            }
+            None => true,
+        }
+    }
+}
+
+// A filter which matches the protected nodes, other than those the filter is allowed to remove.
+// In keep mode, it's added to every filter so that those nodes are kept.
+struct ProtectedFilter {
+    allow_main: bool,
+    allow_exports: bool,
+}
+
+impl Filter for ProtectedFilter {
+    // Returns true if the node is protected, and its protection hasn't been lifted.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        match protection(ctx, node) {
+            Some(_) if self.allow_exports => false,
+            Some(ENTRY_POINT) => !self.allow_main,
+            Some(_) => true,
+            None => false,
+        }
+    }
+}
+
+// A filter which matches whatever its sub-filter matches, and allows the enclosing GuardFilter to
+// remove the protected nodes it matches.
+struct AllowExportsFilter(Box<Filter>);
+
+impl Filter for AllowExportsFilter {
+    // Returns true if the sub-filter does.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        let matched = self.0.apply(ctx, node);
+        if matched {
+            ctx.exports_allowed.set(true);
+        }
+        matched
+    }
+}
+
+// A filter which returns true if an item is the root module of a crate.
+struct RootModFilter;
+
+impl RootModFilter {
+    fn new() -> Box<Filter> {
+        Box::new(RootModFilter {})
+    }
+}
+
+impl Filter for RootModFilter {
+    // Returns true if item is the root module of a crate.
+    fn apply(&self, _ctx: &Context, node: &Node) -> bool {
+        if let &Node::Item(&Item {
+                                ref ident,
+                                node: ItemKind::Mod(..),
+                                ..
+                            }) = node {
+            ident.name.as_str() == ""
+        } else {
+            false
+        }
+    }
+}
+
+// Convenience function for constructing AllFilters.
+fn and(filters: Vec<Box<Filter>>) -> Box<Filter> {
+    let mut v = Vec::new();
+    v.extend(filters);
+    Box::new(AllFilter(v))
+}
+
+// Convenience function for constructing AnyFilters.
+fn or(filters: Vec<Box<Filter>>) -> Box<Filter> {
+    let mut v = Vec::new();
+    v.extend(filters);
+    Box::new(AnyFilter(v))
+}
+
+// Convenience function for constructing OneFilters.
+fn xor(filters: Vec<Box<Filter>>) -> Box<Filter> {
+    let mut v = Vec::new();
+    v.extend(filters);
+    Box::new(OneFilter(v))
+}
+
+// Convenience function for constructing NotFilters.
+fn not(filter: Box<Filter>) -> Box<Filter> {
+    Box::new(NotFilter(filter))
+}
+
+const ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_FILTER";
+// A comma-separated list of presets to apply in addition to any filters in the environment.
+const PRESET_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_PRESET";
+
+// A filter expression, along with a description of where it came from (e.g., the name of an
+// environment variable) for use in error messages.
+pub struct Source {
+    pub origin: String,
+    pub expr: String,
+    // Whether expr is the text the user wrote, rather than an expression generated from it (e.g.,
+    // `include("path")` for `@path`), so that positions in it mean something to them.
+    pub verbatim: bool,
+}
+
+impl Source {
+    pub fn new<S: Into<String>>(origin: S, expr: String) -> Source {
+        Source {
+            origin: origin.into(),
+            expr: expr,
+            verbatim: true,
+        }
+    }
+
+    // Constructs a Source for an expression generated from what the user wrote.
+    pub fn generated<S: Into<String>>(origin: S, expr: String) -> Source {
+        Source { verbatim: false, ..Source::new(origin, expr) }
+    }
+}
+
+// Returns the filter expressions to apply: those in the environment if any are set, or else the
+// default given by the crate (if any), along with warnings about any variables which can't be
+// used. The environment may contain:
+//
+// - RUST_DISABLE_CODE_FILTER, or RUST_DISABLE_CODE_FILTER_<CRATE> (the crate's name in upper
+//   case), which replaces it for a single crate.
+// - Any number of layers, RUST_DISABLE_CODE_FILTER_<N> or RUST_DISABLE_CODE_FILTER_<N>_<NAME>
+//   (e.g., RUST_DISABLE_CODE_FILTER_1_CI), so that each part of a build can contribute its own
+//   expression. Since crate names can't start with a digit, these never name a crate.
+// - RUST_DISABLE_CODE_FILTER_RELEASE or RUST_DISABLE_CODE_FILTER_DEBUG, which only apply to
+//   release and debug builds, respectively.
+// - RUST_DISABLE_CODE_PRESET, a comma-separated list of presets.
+//
+// An environment value of the form `@path` stands for the expression in the named file.
+pub fn sources(default: Option<Source>,
+               crate_name: &str,
+               release: bool)
+               -> (Vec<Source>, Vec<String>) {
+    let prefix = format!("{}_", ENV_VAR_NAME);
+    let crate_var = format!("{}{}", prefix, crate_name.to_uppercase().replace('-', "_"));
+    let profile_var = format!("{}{}", prefix, if release { "RELEASE" } else { "DEBUG" });
+
+    let mut warnings = Vec::new();
+    let mut sources = Vec::new();
+    match (env_var(&crate_var, &mut warnings), env_var(ENV_VAR_NAME, &mut warnings)) {
+        (Some(expr), _) => sources.push(env_source(crate_var, expr)),
+        (None, Some(expr)) => sources.push(env_source(String::from(ENV_VAR_NAME), expr)),
+        (None, None) => {}
+    }
+    let layers = layers(&prefix, &mut warnings);
+    sources.extend(layers.into_iter().map(|(name, expr)| env_source(name, expr)));
+    if let Some(expr) = env_var(&profile_var, &mut warnings) {
+        sources.push(env_source(profile_var, expr));
+    }
+    if let Ok(presets) = env::var(PRESET_ENV_VAR_NAME) {
+        for name in presets.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let expr = format!("preset({})", Expr::Quote(String::from(name)));
+            sources.push(Source::generated(PRESET_ENV_VAR_NAME, expr));
+        }
+    }
+
+    if sources.is_empty() {
+        return (default.into_iter().collect(), warnings);
+    }
+    (sources, warnings)
+}
+
+// Returns the value of the environment variable with the given name, or None if it is not set.
+// A value which isn't valid unicode is ignored with a warning.
+fn env_var(name: &str, warnings: &mut Vec<String>) -> Option<String> {
+    match env::var(name) {
+        Ok(value) => Some(value),
+        Err(env::VarError::NotUnicode(_)) => {
+            warnings.push(format!("ignoring {}, whose value is not valid unicode", name));
+            None
+        }
+        Err(env::VarError::NotPresent) => None,
+    }
+}
+
+// Returns the source for the value of the environment variable with the given name.
+fn env_source(name: String, expr: String) -> Source {
+    if expr.starts_with('@') {
+        // include() already resolves the path relative to the manifest directory, reads the file,
+        // and reports any errors in it
+        let path = Expr::Quote(String::from(&expr[1..]));
+        Source::generated(name, format!("include({})", path))
+    } else {
+        Source::new(name, expr)
+    }
+}
+
+// Returns the names and values of the layered filter variables (those whose names are the prefix
+// followed by a number), in order of their numbers. Warnings are added for variables with the
+// prefix which can't be used.
+fn layers(prefix: &str, warnings: &mut Vec<String>) -> Vec<(String, String)> {
+    let mut layers = Vec::new();
+    // env::vars() would panic on any variable which isn't valid unicode, even an unrelated one
+    for (name, value) in env::vars_os() {
+        let name = match name.into_string() {
+            Ok(name) => name,
+            Err(name) => {
+                let name = name.to_string_lossy();
+                if name.starts_with(prefix) {
+                    warnings.push(format!("ignoring {}, whose name is not valid unicode", name));
+                }
+                continue;
+            }
+        };
+        if !name.starts_with(prefix) {
+            continue;
+        }
+        let n: u64 = {
+            let suffix = &name[prefix.len()..];
+            if suffix.is_empty() {
+                warnings.push(format!("ignoring {}, which has no suffix", name));
+                continue;
+            }
+            let digits = suffix.find(|c: char| !c.is_digit(10)).unwrap_or(suffix.len());
+            if digits == 0 {
+                continue;
+            }
+            suffix[..digits].parse().unwrap_or(u64::max_value())
+        };
+        match value.into_string() {
+            Ok(value) => layers.push((n, name, value)),
+            Err(_) => warnings.push(format!("ignoring {}, whose value is not valid unicode", name)),
+        }
+    }
+    layers.sort();
+    layers.into_iter().map(|(_, name, value)| (name, value)).collect()
+}
+
+// The number of nodes matched by the filter from a single source.
+pub struct MatchCount<'a> {
+    pub source: &'a Source,
+    count: Rc<Cell<usize>>,
+}
+
+impl<'a> MatchCount<'a> {
+    pub fn get(&self) -> usize {
+        self.count.get()
+    }
+}
+
+// A filter which represents the OR of the filters from each source, counting the nodes each one
+// matches. Unlike AnyFilter, every filter is applied to every node, so that the counts are exact.
+// Each filter is paired with the origin of its source, which is recorded in the Context's
+// matched_by.
+struct SourcesFilter(Vec<(Box<Filter>, Rc<Cell<usize>>, String)>);
+
+impl Filter for SourcesFilter {
+    // Returns true if any filter returns true.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        let mut matched_by = None;
+        for &(ref f, ref count, ref origin) in self.0.iter() {
+            if f.apply(ctx, node) {
+                count.set(count.get() + 1);
+                matched_by = matched_by.or(Some(origin));
+            }
+        }
+        let matched = matched_by.is_some();
+        if let Some(origin) = matched_by {
+            *ctx.matched_by.borrow_mut() = Some((ctx.node_path(node), origin.clone()));
+        }
+        if log::enabled(Level::Debug) {
+            let message = format!("{} `{}`: {}",
+                                  node.kind_name(),
+                                  ctx.node_path(node),
+                                  if matched { "matched" } else { "not matched" });
+            log::log(Level::Debug, &message);
+        }
+        matched
+    }
+}
+
+// A filter which logs the result of the filter for a sub-expression each time it's applied.
+struct TraceFilter {
+    filter: Box<Filter>,
+    // The sub-expression, in normalized form.
+    expr: String,
+}
+
+impl Filter for TraceFilter {
+    // Returns the result of the filter.
+    fn apply(&self, ctx: &Context, node: &Node) -> bool {
+        let matched = self.filter.apply(ctx, node);
+        let message = format!("{} on {} `{}`: {}",
+                              self.expr,
+                              node.kind_name(),
+                              ctx.node_path(node),
+                              matched);
+        log::log(Level::Trace, &message);
+        matched
+    }
+}
+
+// Constructs the filter for a set of filter expressions, which matches anything that any of them
+// does, along with a count of the nodes each one matches as the filter is applied. If keep is
+// true, the filter selects the nodes to keep rather than those to remove. If there are no
+// expressions, nothing is removed either way. Errors are returned along with the expression they
+// were found in.
+pub fn to_filter(sources: &[Source],
+                 keep: bool)
+                 -> Result<(Box<Filter>, Vec<MatchCount>), Vec<(&Source, ParseError)>> {
+    let mut filters = Vec::new();
+    let mut counts = Vec::new();
+    let mut errs = Vec::new();
+    for source in sources {
+        match parse_filter(&source.expr, &source.origin, Vec::new(), keep) {
+            Ok(filter) => {
+                let count = Rc::new(Cell::new(0));
+                filters.push((filter, count.clone(), source.origin.clone()));
+                counts.push(MatchCount {
+                                source: source,
+                                count: count,
+                            });
+            }
+            Err(e) => errs.extend(e.into_iter().map(|err| (source, err))),
+        }
+    }
+    if !errs.is_empty() {
+        return Err(errs);
+    }
+    let filter = match filters.len() {
+        0 if keep => AlwaysFilter::new(),
+        0 => NeverFilter::new(),
+        // Never match the root module, which is never removed in either case
+        _ => and(vec![not(RootModFilter::new()), Box::new(SourcesFilter(filters))]),
+    };
+    Ok((filter, counts))
+}
+
+// Validates a set of filter expressions without constructing anything to apply, and returns the
+// normalized form of each.
+pub fn check(sources: &[Source]) -> Result<Vec<(&Source, String)>, Vec<(&Source, ParseError)>> {
+    let mut normalized = Vec::new();
+    let mut errs = Vec::new();
+    for source in sources {
+        let result = parse_expr(&source.expr).and_then(|expr| {
+            parse_filter(&source.expr, &source.origin, Vec::new(), false).map(|_| expr.to_string())
+        });
+        match result {
+            Ok(s) => normalized.push((source, s)),
+            Err(e) => errs.extend(e.into_iter().map(|err| (source, err))),
+        }
+    }
+    if errs.is_empty() {
+        Ok(normalized)
+    } else {
+        Err(errs)
+    }
+}
+
+// Constructs the filter given by a module's `#[disable_code::filter]` attribute, which replaces the
+// enclosing filter within the module. The enclosing filter can still be referred to as
+// `inherited()` (e.g., `and(inherited(), not(tag("regression")))`).
+pub fn module_filter(expr: &str,
+                     inherited: Rc<Box<Filter>>,
+                     keep: bool)
+                     -> Result<Box<Filter>, Vec<ParseError>> {
+    parse_filter(expr,
+                 "a disable_code::filter attribute",
+                 vec![(String::from("inherited"), inherited)],
+                 keep)
+}
+
+// Parses the filter expression from the given origin. Protected nodes (see protection) are never
+// removed unless explicitly allowed; in keep mode they're kept, and otherwise the filter refuses
+// to match them.
+fn parse_filter(filter: &str,
+                origin: &str,
+                bindings: Vec<(String, Rc<Box<Filter>>)>,
+                keep: bool)
+                -> Result<Box<Filter>, Vec<ParseError>> {
+    let mut scope = Scope {
+        bindings: bindings,
+        includes: Vec::new(),
+        allow_remove_main: false,
+        allow_exports: false,
+    };
+    let expr = parse_expr(filter)?;
+    let filter = expr_to_filter(&expr, &mut scope).map_err(|err| vec![err.at(0)])?;
+    if keep {
+        let protected = Box::new(ProtectedFilter {
+                                     allow_main: scope.allow_remove_main,
+                                     allow_exports: scope.allow_exports,
+                                 });
+        Ok(or(vec![protected, filter]))
+    } else {
+        Ok(Box::new(GuardFilter {
+                        filter: filter,
+                        expr: expr.to_string(),
+                        origin: String::from(origin),
+                        allow_main: scope.allow_remove_main,
+                    }))
+    }
+}
+
+// Parses a filter expression, which is written as JSON if it starts with `{`.
+fn parse_expr(filter: &str) -> Result<Expr, Vec<ParseError>> {
+    let expr = if filter.trim_left().starts_with('{') {
+        json::parse(filter)?
+    } else {
+        parse::parse(filter)?
+    };
+    if log::enabled(Level::Info) {
+        log::log(Level::Info, &format!("parsed filter: {}", expr));
+    }
+    Ok(expr)
+}
+
+// The state of the expressions enclosing an expression.
+struct Scope {
+    // The filters bound by the enclosing let() expressions, innermost last.
+    bindings: Vec<(String, Rc<Box<Filter>>)>,
+    // The canonicalized paths of the files being read by the enclosing include() expressions,
+    // innermost last.
+    includes: Vec<PathBuf>,
+    // Whether allow_remove_main() appears anywhere in the expression.
+    allow_remove_main: bool,
+    // Whether unsafe_allow_exports() appears anywhere in the expression.
+    allow_exports: bool,
+}
+
+impl Scope {
+    // Returns the filter bound to the given name by the innermost enclosing let(), if any.
+    fn lookup(&self, name: &str) -> Option<Rc<Box<Filter>>> {
+        for &(ref bound, ref filter) in self.bindings.iter().rev() {
+            if bound == name {
+                return Some(filter.clone());
+            }
+        }
+        None
+    }
+
+    // Returns the directory relative to which include() paths are resolved: the directory
+    // containing the innermost included file, or the crate's manifest directory (falling back
+    // to the current directory) at the top level.
+    fn include_dir(&self) -> PathBuf {
+        if let Some(dir) = self.includes.last().and_then(|file| file.parent()) {
+            return dir.to_path_buf();
+        }
+        match env::var("CARGO_MANIFEST_DIR") {
+            Ok(dir) => PathBuf::from(dir),
+            Err(_) => PathBuf::from("."),
+        }
+    }
+}
+
+fn expr_to_filter(expr: &Expr, scope: &mut Scope) -> Result<Box<Filter>, ParseError> {
+    match expr {
+        &Expr::Quote(_) => Err(ParseError::new("unexpected string argument")),
+        &Expr::Num(_) => Err(ParseError::new("unexpected numeric argument")),
+        &Expr::Call(ref call) => {
+            let filter = call_to_filter(call, scope).map_err(|err| err.at(call.offset))?;
+            if !log::enabled(Level::Trace) {
+                return Ok(filter);
+            }
+            Ok(Box::new(TraceFilter {
+                            filter: filter,
+                            expr: expr.to_string(),
+                        }))
+        }
+    }
+}
+
+fn call_to_filter(call: &Call, scope: &mut Scope) -> Result<Box<Filter>, ParseError> {
+    // names bound by let() shadow the built-in functions
+    if let Some(filter) = scope.lookup(&call.name) {
+        return mk_no_arg_filter(&call.name, &call.args, Box::new(SharedFilter(filter)));
+    }
+
+    match call.name.as_str() {
+        "test" => mk_no_arg_filter("test", &call.args, TestFilter::new()),
+        "bench" => mk_no_arg_filter("bench", &call.args, BenchFilter::new()),
+        "test_mod" => mk_no_arg_filter("test_mod", &call.args, TestModFilter::new()),
+        "regex" => mk_regex_filter(&call.args),
+        "glob" => mk_glob_filter(&call.args),
+        "prefix" => Ok(PrefixFilter::new(single_string_arg("prefix", &call.args)?)),
+        "suffix" => Ok(SuffixFilter::new(single_string_arg("suffix", &call.args)?)),
+        "path" => mk_path_filter(&call.args),
+        "depth" => Ok(DepthFilter::new(single_num_arg("depth", &call.args)? as usize)),
+        "parent" => Ok(ParentFilter::new(single_string_arg("parent", &call.args)?)),
+        "file" => Ok(FileFilter::new(single_string_arg("file", &call.args)?)),
+        "lines" => mk_lines_filter(&call.args),
+        "larger_than" => {
+            Ok(LargerThanFilter::new(single_num_arg("larger_than", &call.args)? as usize))
+        }
+        "doc" => Ok(DocFilter::new(regex_arg("doc", &call.args)?)),
+        "undocumented" => mk_no_arg_filter("undocumented", &call.args, UndocumentedFilter::new()),
+        "cfg" => Ok(CfgFilter::new(single_string_arg("cfg", &call.args)?)),
+        "feature" => mk_feature_filter(&call.args),
+        "derive" => Ok(DeriveFilter::new(single_string_arg("derive", &call.args)?)),
+        "allow" => Ok(AllowFilter::new(single_string_arg("allow", &call.args)?)),
+        "repr" => Ok(ReprFilter::new(single_string_arg("repr", &call.args)?)),
+        "tag" => Ok(TagFilter::new(single_string_arg("tag", &call.args)?)),
+        "attr" => Ok(AttrFilter::new(single_string_arg("attr", &call.args)?)),
+        "attr_value" => mk_attr_value_filter(&call.args),
+        "kind" => mk_kind_filter(&call.args),
+        "vis" => mk_vis_filter(&call.args),
+        "fn" => mk_no_arg_filter("fn", &call.args, FnFilter::new()),
+        "static_item" => mk_named_kind_filter("static_item", "static", &call.args),
+        "const_item" => mk_named_kind_filter("const_item", "const", &call.args),
+        "module" => mk_module_filter(&call.args),
+        "types" => mk_types_filter(&call.args),
+        "use_item" => mk_no_arg_filter("use_item", &call.args, KindFilter::new("use")),
+        "variant" => mk_named_kind_filter("variant", "variant", &call.args),
+        "arity" => Ok(ArityFilter::new(single_num_arg("arity", &call.args)? as usize)),
+        "returns" => Ok(ReturnsFilter::new(regex_arg("returns", &call.args)?)),
+        "body_contains" => Ok(BodyContainsFilter::new(regex_arg("body_contains", &call.args)?)),
+        "calls" => Ok(CallsFilter::new(single_string_arg("calls", &call.args)?)),
+        "module_items_over" => {
+            let n = single_num_arg("module_items_over", &call.args)?;
+            Ok(ModuleItemsOverFilter::new(n as usize))
+        }
+        "generic" => mk_no_arg_filter("generic", &call.args, GenericFilter::new()),
+        // The compiler versions this plugin supports have no notion of async functions (the
+        // function header only records unsafety, constness, and ABI), so there is nothing for
+        // async_fn() to match on.
+        "async_fn" => Err(ParseError::new("async_fn() is not supported by this compiler version")),
+        "unsafe_fn" => mk_no_arg_filter("unsafe_fn", &call.args, UnsafeFnFilter::new()),
+        "impl_of" => Ok(ImplOfFilter::new(regex_arg("impl_of", &call.args)?)),
+        "impl_for" => Ok(ImplForFilter::new(regex_arg("impl_for", &call.args)?)),
+        "macro_def" => mk_no_arg_filter("macro_def", &call.args, MacroDefFilter::new()),
+        "no_mangle" => mk_no_arg_filter("no_mangle", &call.args, NoMangleFilter::new()),
+        "exported" => mk_no_arg_filter("exported", &call.args, ExportedFilter::new()),
+        "foreign" => mk_no_arg_filter("foreign", &call.args, ForeignFilter::new()),
+        "crate" => Ok(CrateFilter::new(single_string_arg("crate", &call.args)?)),
+        "target" => mk_target_filter(&call.args),
+        "target_os" => Ok(TargetFilter::new("os", single_string_arg("target_os", &call.args)?)),
+        "target_arch" => {
+            Ok(TargetFilter::new("arch", single_string_arg("target_arch", &call.args)?))
+        }
+        "env" => mk_env_filter(&call.args),
+        "main" => mk_no_arg_filter("main", &call.args, MainFilter::new()),
+        // allow_remove_main() matches nothing, but its presence anywhere in the filter lifts the
+        // protection of the entry point (e.g., or(main(), allow_remove_main())).
+        "allow_remove_main" => {
+            scope.allow_remove_main = true;
+            mk_no_arg_filter("allow_remove_main", &call.args, NeverFilter::new())
+        }
+        // unsafe_allow_exports(f) matches whatever f does, including protected nodes. In keep mode,
+        // its presence anywhere lifts the protection instead, as for allow_remove_main().
+        "unsafe_allow_exports" => {
+            scope.allow_exports = true;
+            check_arg_count("unsafe_allow_exports", &call.args, 1)?;
+            Ok(Box::new(AllowExportsFilter(expr_to_filter(&call.args[0], scope)?)))
+        }
+        "true" => mk_no_arg_filter("true", &call.args, AlwaysFilter::new()),
+        "false" => mk_no_arg_filter("false", &call.args, NeverFilter::new()),
+        "and" => mk_and_filter(&call.args, scope),
+        "or" => mk_or_filter(&call.args, scope),
+        "xor" => mk_xor_filter(&call.args, scope),
+        "not" => mk_not_filter(&call.args, scope),
+        "if" => mk_if_filter(&call.args, scope),
+        "let" => mk_let_filter(&call.args, scope),
+        "include" => mk_include_filter(&call.args, scope),
+        "preset" => mk_preset_filter(&call.args),
+        s => Err(ParseError::new(format!("unrecognized function: {}", s))),
+    }
+}
+
+// regex("re", "flags") matches item names against a regex compiled with the given flags: `i` for
+// case-insensitive matching, `m` for `^` and `$` matching at line boundaries, and `s` for `.`
+// matching newlines (e.g., regex("^foo", "i")). The flags argument is optional.
+fn mk_regex_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    match args.len() {
+        1 => return Ok(RegexFilter::new(regex_arg("regex", args)?)),
+        2 => {}
+        _ => return Err(ParseError::new("regex() takes 1 or 2 arguments")),
+    }
+    let args = string_args("regex", args, 2)?;
+    let mut builder = RegexBuilder::new(args[0]);
+    for flag in args[1].chars() {
+        match flag {
+            'i' => builder.case_insensitive(true),
+            'm' => builder.multi_line(true),
+            's' => builder.dot_matches_new_line(true),
+            _ => return Err(ParseError::new(format!("regex(): unrecognized flag: {}", flag))),
+        };
+    }
+    match builder.build() {
+        Ok(re) => Ok(RegexFilter::new(re)),
+        Err(err) => {
+            let message = format!("regex(): could not parse argument {:?}: {}", args[0], err);
+            Err(ParseError::new(message))
+        }
+    }
+}
+
+// glob("pattern") matches item names against a shell-style glob, where `*` matches any sequence of
+// characters and `?` matches any single character.
+fn mk_glob_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    Ok(RegexFilter::new(glob_to_regex(single_string_arg("glob", args)?, None)))
+}
+
+fn mk_path_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    Ok(PathFilter::new(regex_arg("path", args)?))
+}
+
+fn mk_attr_value_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    let args = string_args("attr_value", args, 2)?;
+    Ok(AttrValueFilter::new(args[0], args[1]))
+}
+
+fn mk_kind_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    let kind = single_string_arg("kind", args)?;
+    match KINDS.iter().find(|k| **k == kind) {
+        Some(k) => Ok(KindFilter::new(k)),
+        None => {
+            Err(ParseError::new(format!("kind(): unrecognized kind: {} (expected one of {})",
+                                        kind,
+                                        KINDS.join(", "))))
+        }
+    }
+}
+
+// Constructs a filter for a predicate which matches items of the given kind, optionally restricted
+// to items whose names match a regex argument.
+fn mk_named_kind_filter(name: &str,
+                        kind: &'static str,
+                        args: &Vec<Expr>)
+                        -> Result<Box<Filter>, ParseError> {
+    match args.len() {
+        0 => Ok(KindFilter::new(kind)),
+        1 => Ok(and(vec![KindFilter::new(kind), RegexFilter::new(regex_arg(name, args)?)])),
+        _ => Err(ParseError::new(format!("{}() takes 0 or 1 arguments", name))),
+    }
+}
+
+// module("re") is shorthand for and(kind("mod"), regex("re")). The root module is never matched
+// because to_filter protects it, even though its empty name may match the regex.
+fn mk_module_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    Ok(and(vec![KindFilter::new("mod"), RegexFilter::new(regex_arg("module", args)?)]))
+}
+
+// types() is shorthand for or(kind("struct"), kind("enum"), kind("union"), kind("type")).
+fn mk_types_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    let types = or(vec![KindFilter::new("struct"),
+                        KindFilter::new("enum"),
+                        KindFilter::new("union"),
+                        KindFilter::new("type")]);
+    mk_no_arg_filter("types", args, types)
+}
+
+fn mk_vis_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    let vis = single_string_arg("vis", args)?;
+    match VISIBILITIES.iter().find(|v| **v == vis) {
+        Some(v) => Ok(VisFilter::new(v)),
+        None => {
+            Err(ParseError::new(format!("vis(): unrecognized visibility: {} (expected one of {})",
+                                        vis,
+                                        VISIBILITIES.join(", "))))
+        }
+    }
+}
+
+// feature("name") is shorthand for cfg("feature = \"name\"").
+fn mk_feature_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    Ok(FeatureFilter::new(single_string_arg("feature", args)?))
+}
+
+fn mk_lines_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    let args = num_args("lines", args, 2)?;
+    if args[0] > args[1] {
+        return Err(ParseError::new(format!("lines(): start line {} is after end line {}",
+                                           args[0],
+                                           args[1])));
+    }
+    Ok(LinesFilter::new(args[0] as usize, args[1] as usize))
+}
+
+// target("key", "value") matches when `target_key = "value"` is set for the compilation (e.g.,
+// target("pointer_width", "64")).
+fn mk_target_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    let args = string_args("target", args, 2)?;
+    Ok(TargetFilter::new(args[0], args[1]))
+}
+
+// env("VAR", "value") matches everything if the environment variable VAR is set to value, and
+// nothing otherwise. env("VAR") matches everything if VAR is set at all. The environment is read
+// once, when the filter is constructed.
+fn mk_env_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    let matches = match args.len() {
+        1 => env::var(single_string_arg("env", args)?).is_ok(),
+        2 => {
+            let args = string_args("env", args, 2)?;
+            match env::var(args[0]) {
+                Ok(value) => value == args[1],
+                Err(_) => false,
+            }
+        }
+        _ => return Err(ParseError::new("env() takes 1 or 2 arguments")),
+    };
+    if matches {
+        Ok(AlwaysFilter::new())
+    } else {
+        Ok(NeverFilter::new())
+    }
+}
+
+fn mk_no_arg_filter(name: &str,
+                    args: &Vec<Expr>,
+                    filter: Box<Filter>)
+                    -> Result<Box<Filter>, ParseError> {
+    if args.len() != 0 {
+        return Err(ParseError::new(format!("{}() takes no arguments", name)));
+    }
+    Ok(filter)
+}
+
+fn mk_and_filter(args: &Vec<Expr>, scope: &mut Scope) -> Result<Box<Filter>, ParseError> {
+    if args.len() == 0 {
+        return Err(ParseError::new("and() takes 1 or more arguments"));
+    }
+    Ok(and(args_to_filters(args, scope)?))
+}
+
+fn mk_or_filter(args: &Vec<Expr>, scope: &mut Scope) -> Result<Box<Filter>, ParseError> {
+    if args.len() == 0 {
+        return Err(ParseError::new("or() takes 1 or more arguments"));
+    }
+    Ok(or(args_to_filters(args, scope)?))
+}
+
+fn mk_xor_filter(args: &Vec<Expr>, scope: &mut Scope) -> Result<Box<Filter>, ParseError> {
+    if args.len() == 0 {
+        return Err(ParseError::new("xor() takes 1 or more arguments"));
+    }
+    Ok(xor(args_to_filters(args, scope)?))
+}
+
+fn mk_not_filter(args: &Vec<Expr>, scope: &mut Scope) -> Result<Box<Filter>, ParseError> {
+    if args.len() != 1 {
+        return Err(ParseError::new("not() takes 1 argument"));
+    }
+    Ok(not(expr_to_filter(&args[0], scope)?))
+}
+
+fn mk_if_filter(args: &Vec<Expr>, scope: &mut Scope) -> Result<Box<Filter>, ParseError> {
+    if args.len() != 3 {
+        return Err(ParseError::new("if() takes 3 arguments"));
+    }
+    Ok(Box::new(CondFilter {
+                    cond: expr_to_filter(&args[0], scope)?,
+                    then: expr_to_filter(&args[1], scope)?,
+                    els: expr_to_filter(&args[2], scope)?,
+                }))
+}
+
+// let("name", value, body) binds value to name while constructing body, where it can be referred
+// to as name(). The value is only constructed once, and is shared by all references to it.
+fn mk_let_filter(args: &Vec<Expr>, scope: &mut Scope) -> Result<Box<Filter>, ParseError> {
+    if args.len() != 3 {
+        return Err(ParseError::new("let() takes 3 arguments"));
+    }
+    let bound = if let Expr::Quote(ref s) = args[0] {
+        s.clone()
+    } else {
+        return Err(ParseError::new("let() takes a string as its first argument"));
+    };
+    // the bound name must be a valid function name, or else it could never be referred to
+    if !parse::is_name(&bound) {
+        return Err(ParseError::new(format!("let(): invalid name: {}", bound)));
+    }
+
+    let value = Rc::new(expr_to_filter(&args[1], scope)?);
+    scope.bindings.push((bound, value));
+    let body = expr_to_filter(&args[2], scope);
+    scope.bindings.pop();
+    body
+}
+
+// preset("name") stands for one of the expressions in PRESETS. It is constructed in a scope of its
+// own, so that names bound by an enclosing let() can't change its meaning.
+fn mk_preset_filter(args: &Vec<Expr>) -> Result<Box<Filter>, ParseError> {
+    let name = single_string_arg("preset", args)?;
+    let expr = match PRESETS.iter().find(|&&(n, _)| n == name) {
+        Some(&(_, expr)) => expr,
+        None => {
+            let names: Vec<&str> = PRESETS.iter().map(|&(n, _)| n).collect();
+            let message = format!("preset(): unrecognized preset: {} (expected one of {})",
+                                  name,
+                                  names.join(", "));
+            return Err(ParseError::new(message));
+        }
+    };
+    let mut scope = Scope {
+        bindings: Vec::new(),
+        includes: Vec::new(),
+        allow_remove_main: false,
+        allow_exports: false,
+    };
+    let expr = parse::parse(expr).map_err(|mut errs| errs.swap_remove(0))?;
+    expr_to_filter(&expr, &mut scope)
+}
+
+// include("path") reads the filter expression in the given file and splices it in place of the
+// call. Relative paths are resolved as described in Scope::include_dir. Errors in the included
+// expression are reported at the call, with the offset in the file as part of the message.
+fn mk_include_filter(args: &Vec<Expr>, scope: &mut Scope) -> Result<Box<Filter>, ParseError> {
+    let path = scope.include_dir().join(single_string_arg("include", args)?);
+    let path = match fs::canonicalize(&path) {
+        Ok(path) => path,
+        Err(err) => {
+            return Err(ParseError::new(format!("include(): could not find {}: {}",
+                                               path.display(),
+                                               err)))
+        }
+    };
+    if scope.includes.contains(&path) {
+        let mut cycle: Vec<String> = scope.includes
+            .iter()
+            .skip_while(|p| **p != path)
+            .map(|p| p.display().to_string())
+            .collect();
+        cycle.push(path.display().to_string());
+        return Err(ParseError::new(format!("include(): cycle detected: {}", cycle.join(" -> "))));
+    }
+
+    let mut filter = String::new();
+    if let Err(err) = File::open(&path).and_then(|mut f| f.read_to_string(&mut filter)) {
+        return Err(ParseError::new(format!("include(): could not read {}: {}",
+                                           path.display(),
+                                           err)));
+    }
+    let in_file = |err: ParseError| {
+        ParseError::new(format!("in {}: {}", path.display(), err.at(0)))
+    };
+    // only the first error in the file is reported
+    let expr = parse_expr(&filter).map_err(|mut errs| in_file(errs.swap_remove(0)))?;
+    scope.includes.push(path.clone());
+    let filter = expr_to_filter(&expr, scope).map_err(&in_file);
+    scope.includes.pop();
+    filter
+}
+
+// Returns the argument of a call which takes exactly one string argument.
+fn single_string_arg<'a>(name: &str, args: &'a Vec<Expr>) -> Result<&'a str, ParseError> {
+    Ok(string_args(name, args, 1)?[0])
+}
+
+// Returns the argument of a call which takes exactly one string argument, parsed as a regex.
+fn regex_arg(name: &str, args: &Vec<Expr>) -> Result<Regex, ParseError> {
+    let arg = single_string_arg(name, args)?;
+    Regex::new(arg).map_err(|err| {
+        ParseError::new(format!("{}(): could not parse argument {:?}: {}", name, arg, err))
+    })
+}
+
+// Returns the argument of a call which takes exactly one numeric argument.
+fn single_num_arg(name: &str, args: &Vec<Expr>) -> Result<u64, ParseError> {
+    Ok(num_args(name, args, 1)?[0])
+}
+
+// Returns the arguments of a call which takes exactly n string arguments.
+fn string_args<'a>(name: &str, args: &'a Vec<Expr>, n: usize) -> Result<Vec<&'a str>, ParseError> {
+    check_arg_count(name, args, n)?;
+    let mut v = Vec::new();
+    for arg in args {
+        if let &Expr::Quote(ref s) = arg {
+            v.push(s.as_str());
+        } else if n == 1 {
+            return Err(ParseError::new(format!("{}() only takes a string argument", name)));
+        } else {
+            return Err(ParseError::new(format!("{}() only takes string arguments", name)));
+        }
+    }
+    Ok(v)
+}
+
+// Returns the arguments of a call which takes exactly n numeric arguments.
+fn num_args(name: &str, args: &Vec<Expr>, n: usize) -> Result<Vec<u64>, ParseError> {
+    check_arg_count(name, args, n)?;
+    let mut v = Vec::new();
+    for arg in args {
+        if let &Expr::Num(n) = arg {
+            v.push(n);
+        } else if n == 1 {
+            return Err(ParseError::new(format!("{}() only takes a numeric argument", name)));
+        } else {
+            return Err(ParseError::new(format!("{}() only takes numeric arguments", name)));
+        }
+    }
+    Ok(v)
+}
+
+fn check_arg_count(name: &str, args: &Vec<Expr>, n: usize) -> Result<(), ParseError> {
+    if args.len() == n {
+        Ok(())
+    } else if n == 1 {
+        Err(ParseError::new(format!("{}() takes 1 argument", name)))
+    } else {
+        Err(ParseError::new(format!("{}() takes {} arguments", name, n)))
+    }
+}
+
+fn args_to_filters(args: &Vec<Expr>, scope: &mut Scope) -> Result<Vec<Box<Filter>>, ParseError> {
+    let mut v = Vec::new();
+    for arg in args {
+        v.push(expr_to_filter(arg, scope)?);
+    }
+    Ok(v)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Returns true if a callee written as callee matches calls(target).
+    fn matches(target: &str, callee: &str) -> bool {
+        let split = |path: &str| path.split("::").map(String::from).collect::<Vec<_>>();
+        call_matches(&split(target), &split(callee))
+    }
+
+    #[test]
+    fn test_call_matches() {
+        let target = "std::process::Command::new";
+        assert!(matches(target, "std::process::Command::new"));
+        assert!(matches(target, "::std::process::Command::new"));
+        assert!(matches(target, "process::Command::new"));
+        assert!(matches(target, "Command::new"));
+        assert!(!matches(target, "new"));
+        assert!(!matches(target, "Other::new"));
+        assert!(!matches("Command::new", "std::process::Command::new"));
+    }
+
+    #[test]
+    fn test_call_matches_relative() {
+        assert!(matches("foo", "foo"));
+        assert!(matches("foo", "self::foo"));
+        assert!(matches("foo", "super::foo"));
+        assert!(matches("bar::foo", "self::bar::foo"));
+        assert!(matches("crate::bar::foo", "bar::foo"));
+        assert!(!matches("bar::foo", "self::foo"));
+        assert!(!matches("foo", "self"));
+        assert!(!matches("foo", "bar"));
+    }
+}
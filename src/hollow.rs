@@ -0,0 +1,89 @@
+// Hollow mode, which stubs out the body of every function and method left after filtering
+// (including the default implementations of trait methods) while keeping every item's signature.
+// The result type-checks like the original crate at a fraction of the cost of compiling it, for
+// "header" crates used by IDEs and check pipelines.
+//
+// The bodies of const fns and the initializers of consts and statics are kept, since a constant
+// expression can't panic (and so can't be replaced by `unimplemented!()`).
+
+use syntax::ast::{Item, ItemKind, Mod};
+use syntax::ext::base::ExtCtxt;
+
+use filter::{Context, Node};
+use {attrs, is_cfg_false, stub_fn, stub_method, stub_trait_method, with_item_mut, Action, Removal};
+
+use std::collections::HashSet;
+
+// Stubs out every function and method in the crate which hasn't been removed or stubbed out
+// already, recording each one in removals.
+pub fn hollow(cx: &ExtCtxt, root: &mut Item, removals: &mut Vec<Removal>) {
+    let stubbed: HashSet<String> = removals.iter().map(|r| r.path.clone()).collect();
+    let mut ctx = Context::new(cx, root);
+    if let ItemKind::Mod(ref mut md) = root.node {
+        hollow_mod(cx, &mut ctx, &stubbed, md, removals);
+    }
+}
+
+fn hollow_mod(cx: &ExtCtxt,
+              ctx: &mut Context,
+              stubbed: &HashSet<String>,
+              md: &mut Mod,
+              removals: &mut Vec<Removal>) {
+    for item in md.items.iter_mut() {
+        with_item_mut(item, |it| hollow_item(cx, ctx, stubbed, removals, it));
+    }
+}
+
+fn hollow_item(cx: &ExtCtxt,
+               ctx: &mut Context,
+               stubbed: &HashSet<String>,
+               removals: &mut Vec<Removal>,
+               it: &mut Item) {
+    // items marked with #[disable_code::keep] are left alone, as are those disabled by the filter
+    if attrs::is_kept(&it.attrs) || it.attrs.iter().any(is_cfg_false) {
+        return;
+    }
+    let name = String::from(&it.ident.name.as_str() as &str);
+    match it.node {
+        ItemKind::Mod(ref mut inner) => {
+            ctx.push_mod(&name);
+            hollow_mod(cx, ctx, stubbed, inner, removals);
+            ctx.pop_mod();
+            return;
+        }
+        ItemKind::Impl(_, _, _, _, ref trait_ref, ref self_ty, ref mut items) => {
+            ctx.push_impl(self_ty, trait_ref.is_some());
+            for item in items.iter_mut() {
+                if attrs::is_kept(&item.attrs) || item.attrs.iter().any(is_cfg_false) {
+                    continue;
+                }
+                // recorded before stubbing out, as in filter_child, so prune sees the old body
+                let removal = Removal::new(ctx, &Node::ImplItem(item), Action::Stub);
+                if !stubbed.contains(&removal.path) && stub_method(cx, item, None) {
+                    removals.push(removal);
+                }
+            }
+            ctx.pop_owner();
+            return;
+        }
+        ItemKind::Trait(_, _, _, ref mut items) => {
+            ctx.push_owner(&name);
+            for item in items.iter_mut() {
+                if attrs::is_kept(&item.attrs) || item.attrs.iter().any(is_cfg_false) {
+                    continue;
+                }
+                let removal = Removal::new(ctx, &Node::TraitItem(item), Action::Stub);
+                if !stubbed.contains(&removal.path) && stub_trait_method(cx, item) {
+                    removals.push(removal);
+                }
+            }
+            ctx.pop_owner();
+            return;
+        }
+        _ => {}
+    }
+    let removal = Removal::new(ctx, &Node::Item(it), Action::Stub);
+    if !stubbed.contains(&removal.path) && stub_fn(cx, it, None) {
+        removals.push(removal);
+    }
+}
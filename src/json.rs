@@ -0,0 +1,410 @@
+// A JSON representation of filter expressions, for tools which generate filters. Each call is an
+// object with a single key naming the function, whose value gives the arguments: an array of
+// arguments, or a single string, number, or call, or an empty object, empty array, or null for
+// no arguments. For example, `{"or": [{"test": {}}, {"regex": "^dbg_"}]}` is the same as
+// `or(test(), regex("^dbg_"))`.
+
+use parse::{self, Call, Expr, ParseError};
+
+use std::char;
+
+// Parses a filter expression written as JSON.
+pub fn parse(input: &str) -> Result<Expr, Vec<ParseError>> {
+    let mut parser = Parser {
+        input: input,
+        pos: 0,
+    };
+    let result = parser.value().and_then(|value| {
+        parser.skip_space();
+        if parser.pos < input.len() {
+            return Err(ParseError::new("unexpected characters after JSON value").at(parser.pos));
+        }
+        to_call(value)
+    });
+    result.map(Expr::Call).map_err(|err| vec![err])
+}
+
+struct Value {
+    // The byte offset of the value in the input.
+    offset: usize,
+    kind: Kind,
+}
+
+enum Kind {
+    Null,
+    Boolean,
+    Number(u64),
+    String(String),
+    Array(Vec<Value>),
+    // The members of an object, each paired with the offset of its key.
+    Object(Vec<(usize, String, Value)>),
+}
+
+impl Kind {
+    fn name(&self) -> &'static str {
+        match self {
+            &Kind::Null => "null",
+            &Kind::Boolean => "boolean",
+            &Kind::Number(_) => "number",
+            &Kind::String(_) => "string",
+            &Kind::Array(_) => "array",
+            &Kind::Object(_) => "object",
+        }
+    }
+}
+
+// Converts an object representing a call.
+fn to_call(value: Value) -> Result<Call, ParseError> {
+    let mut members = match value.kind {
+        Kind::Object(members) => members,
+        kind => {
+            let message = format!("expected an object for a call, found {}", kind.name());
+            return Err(ParseError::new(message).at(value.offset));
+        }
+    };
+    if members.len() != 1 {
+        let message = "an object for a call must have exactly one key, the function's name";
+        return Err(ParseError::new(message).at(value.offset));
+    }
+    let (offset, name, args) = members.pop().unwrap();
+    if !parse::is_name(&name) {
+        return Err(ParseError::new(format!("invalid function name: {:?}", name)).at(offset));
+    }
+    let args = match args {
+        Value { kind: Kind::Null, .. } => Vec::new(),
+        Value { kind: Kind::Array(values), .. } => {
+            let mut args = Vec::new();
+            for value in values {
+                args.push(to_arg(value)?);
+            }
+            args
+        }
+        Value { kind: Kind::Object(ref members), .. } if members.is_empty() => Vec::new(),
+        value => vec![to_arg(value)?],
+    };
+    Ok(Call {
+           name: name,
+           args: args,
+           offset: offset,
+       })
+}
+
+fn to_arg(value: Value) -> Result<Expr, ParseError> {
+    match value.kind {
+        Kind::String(s) => Ok(Expr::Quote(s)),
+        Kind::Number(n) => Ok(Expr::Num(n)),
+        Kind::Object(_) => to_call(value).map(Expr::Call),
+        kind => {
+            let message = format!("expected a string, number, or call, found {}", kind.name());
+            Err(ParseError::new(message).at(value.offset))
+        }
+    }
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    fn error<S: Into<String>>(&self, message: S) -> ParseError {
+        ParseError::new(message).at(self.pos)
+    }
+
+    // Consumes s if the input continues with it, and returns whether it did.
+    fn eat(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn skip_space(&mut self) {
+        let len = self.rest()
+            .find(|c| !(c == ' ' || c == '\t' || c == '\r' || c == '\n'))
+            .unwrap_or(self.rest().len());
+        self.pos += len;
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.skip_space();
+        let offset = self.pos;
+        let kind = match self.peek() {
+            Some('{') => self.object()?,
+            Some('[') => self.array()?,
+            Some('"') => Kind::String(self.string()?),
+            Some('0'...'9') => self.number()?,
+            Some('-') => return Err(self.error("negative numbers are not allowed")),
+            _ if self.eat("null") => Kind::Null,
+            _ if self.eat("true") || self.eat("false") => Kind::Boolean,
+            Some(c) => return Err(self.error(format!("unexpected character `{}`", c))),
+            None => return Err(self.error("expected a JSON value, found end of input")),
+        };
+        Ok(Value {
+               offset: offset,
+               kind: kind,
+           })
+    }
+
+    // Parses a comma-separated sequence of elements between open and close, calling element to
+    // parse each one.
+    fn sequence<F>(&mut self, open: &str, close: &str, mut element: F) -> Result<(), ParseError>
+        where F: FnMut(&mut Parser<'a>) -> Result<(), ParseError>
+    {
+        self.eat(open);
+        self.skip_space();
+        if self.eat(close) {
+            return Ok(());
+        }
+        loop {
+            element(self)?;
+            self.skip_space();
+            if self.eat(close) {
+                return Ok(());
+            }
+            if !self.eat(",") {
+                return Err(self.error(format!("expected `,` or `{}`", close)));
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Kind, ParseError> {
+        let mut members = Vec::new();
+        self.sequence("{", "}", |parser| {
+                parser.skip_space();
+                let offset = parser.pos;
+                if parser.peek() != Some('"') {
+                    return Err(parser.error("expected a string key"));
+                }
+                let key = parser.string()?;
+                if members.iter().any(|&(_, ref k, _)| *k == key) {
+                    return Err(ParseError::new(format!("duplicate key {:?}", key)).at(offset));
+                }
+                parser.skip_space();
+                if !parser.eat(":") {
+                    return Err(parser.error("expected `:`"));
+                }
+                let value = parser.value()?;
+                members.push((offset, key, value));
+                Ok(())
+            })?;
+        Ok(Kind::Object(members))
+    }
+
+    fn array(&mut self) -> Result<Kind, ParseError> {
+        let mut values = Vec::new();
+        self.sequence("[", "]", |parser| {
+                values.push(parser.value()?);
+                Ok(())
+            })?;
+        Ok(Kind::Array(values))
+    }
+
+    // Parses a non-negative integer. Fractions and exponents are not allowed, since no function
+    // takes anything other than an integer.
+    fn number(&mut self) -> Result<Kind, ParseError> {
+        let offset = self.pos;
+        let len = self.rest().find(|c: char| !c.is_digit(10)).unwrap_or(self.rest().len());
+        let digits = &self.rest()[..len];
+        self.pos += len;
+        match self.peek() {
+            Some('.') | Some('e') | Some('E') => {
+                return Err(self.error("only integers are allowed"));
+            }
+            _ => {}
+        }
+        match digits.parse() {
+            Ok(n) => Ok(Kind::Number(n)),
+            Err(_) => Err(ParseError::new(format!("number is too large: {}", digits)).at(offset)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        self.eat("\"");
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(ParseError::new("unterminated string").at(start)),
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some('\\') => s.push(self.escape()?),
+                Some(c) if c < ' ' => {
+                    return Err(self.error("control characters must be escaped in strings"));
+                }
+                Some(c) => {
+                    s.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+    }
+
+    // Parses an escape sequence in a string.
+    fn escape(&mut self) -> Result<char, ParseError> {
+        let start = self.pos;
+        self.pos += 1;
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(ParseError::new("unterminated string").at(start)),
+        };
+        self.pos += c.len_utf8();
+        match c {
+            '"' => Ok('"'),
+            '\\' => Ok('\\'),
+            '/' => Ok('/'),
+            'b' => Ok('\u{8}'),
+            'f' => Ok('\u{c}'),
+            'n' => Ok('\n'),
+            'r' => Ok('\r'),
+            't' => Ok('\t'),
+            'u' => {
+                let high = self.hex_escape(start)?;
+                // characters outside of the basic multilingual plane are written as a pair of
+                // UTF-16 surrogates
+                let code = if 0xd800 <= high && high < 0xdc00 && self.eat("\\u") {
+                    match self.hex_escape(start)? {
+                        low @ 0xdc00...0xdfff => 0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00),
+                        _ => return Err(ParseError::new("invalid unicode escape").at(start)),
+                    }
+                } else {
+                    high
+                };
+                char::from_u32(code)
+                    .ok_or_else(|| ParseError::new("invalid unicode escape").at(start))
+            }
+            _ => Err(ParseError::new(format!("invalid escape sequence: \\{}", c)).at(start)),
+        }
+    }
+
+    // Parses the four hexadecimal digits of a `\u` escape sequence.
+    fn hex_escape(&mut self, start: usize) -> Result<u32, ParseError> {
+        let hex: String = self.rest().chars().take(4).collect();
+        match u32::from_str_radix(&hex, 16) {
+            Ok(code) if hex.len() == 4 && hex.chars().all(|c| c.is_digit(16)) => {
+                self.pos += 4;
+                Ok(code)
+            }
+            _ => Err(ParseError::new("invalid unicode escape").at(start)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parses input, panicking if it is invalid, and returns the expression in normalized form.
+    fn normalize(input: &str) -> String {
+        match parse(input) {
+            Ok(expr) => expr.to_string(),
+            Err(errors) => panic!("failed to parse {:?}: {:?}", input, errors),
+        }
+    }
+
+    // Parses input, panicking if it is valid, and returns the offset and message of the error.
+    fn error(input: &str) -> (Option<usize>, String) {
+        match parse(input) {
+            Ok(expr) => panic!("unexpectedly parsed {:?} as {}", input, expr),
+            Err(mut errors) => {
+                assert_eq!(errors.len(), 1);
+                let error = errors.pop().unwrap();
+                (error.offset, error.message)
+            }
+        }
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(normalize(r#"{"or": [{"test": {}}, {"regex": "^dbg_"}]}"#),
+                   r#"or(test(), regex("^dbg_"))"#);
+        assert_eq!(normalize(r#"{"lines": [1, 20]}"#), "lines(1, 20)");
+        assert_eq!(normalize(r#"{"not": {"test": null}}"#), "not(test())");
+        assert_eq!(normalize(r#"{"bench": []}"#), "bench()");
+        assert_eq!(normalize(r#"{"larger_than": 50}"#), "larger_than(50)");
+        assert_eq!(normalize(" \n{ \"test\" : { } }\t"), "test()");
+    }
+
+    #[test]
+    fn test_offsets() {
+        match parse(r#"{"and": [{"test": {}}, {"name": "x"}]}"#) {
+            Ok(Expr::Call(ref and)) => {
+                assert_eq!(and.offset, 1);
+                match and.args[1] {
+                    Expr::Call(ref name) => assert_eq!(name.offset, 24),
+                    ref other => panic!("unexpected {:?}", other),
+                }
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(normalize(r#"{"name": "a\"b\\c\/d\n"}"#), "name(\"a\\\"b\\\\c/d\n\")");
+        assert_eq!(normalize(r#"{"name": "é😀"}"#), "name(\"\u{e9}\u{1f600}\")");
+        assert_eq!(error(r#"{"name": "\ud83dA"}"#),
+                   (Some(10), String::from("invalid unicode escape")));
+        assert_eq!(error(r#"{"name": "\u12"}"#),
+                   (Some(10), String::from("invalid unicode escape")));
+        assert_eq!(error(r#"{"name": "\q"}"#),
+                   (Some(10), String::from("invalid escape sequence: \\q")));
+        assert_eq!(error("{\"name\": \"a\nb\"}"),
+                   (Some(11), String::from("control characters must be escaped in strings")));
+        assert_eq!(error(r#"{"name": "abc"#), (Some(9), String::from("unterminated string")));
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_eq!(error(r#"{"lines": [-1, 2]}"#),
+                   (Some(11), String::from("negative numbers are not allowed")));
+        assert_eq!(error(r#"{"larger_than": 1.5}"#),
+                   (Some(17), String::from("only integers are allowed")));
+        assert_eq!(error(r#"{"larger_than": 1e3}"#),
+                   (Some(17), String::from("only integers are allowed")));
+        assert_eq!(error(r#"{"larger_than": 99999999999999999999}"#),
+                   (Some(16), String::from("number is too large: 99999999999999999999")));
+    }
+
+    #[test]
+    fn test_call_errors() {
+        assert_eq!(error(r#""test""#),
+                   (Some(0), String::from("expected an object for a call, found string")));
+        assert_eq!(error(r#"{}"#),
+                   (Some(0),
+                    String::from("an object for a call must have exactly one key, the function's \
+                                  name")));
+        assert_eq!(error(r#"{"Test": {}}"#),
+                   (Some(1), String::from("invalid function name: \"Test\"")));
+        assert_eq!(error(r#"{"not": [true]}"#),
+                   (Some(9), String::from("expected a string, number, or call, found boolean")));
+        assert_eq!(error(r#"{"not": [[]]}"#),
+                   (Some(9), String::from("expected a string, number, or call, found array")));
+    }
+
+    #[test]
+    fn test_syntax_errors() {
+        assert_eq!(error(r#"{"test": {}} x"#),
+                   (Some(13), String::from("unexpected characters after JSON value")));
+        assert_eq!(error(r#"{"a": 1, "a": 2}"#), (Some(9), String::from("duplicate key \"a\"")));
+        assert_eq!(error(r#"{test: {}}"#), (Some(1), String::from("expected a string key")));
+        assert_eq!(error(r#"{"test" {}}"#), (Some(8), String::from("expected `:`")));
+        assert_eq!(error(r#"{"or": [{"test": {}} {"bench": {}}]}"#),
+                   (Some(21), String::from("expected `,` or `]`")));
+        assert_eq!(error(r#"{"test": {}"#), (Some(11), String::from("expected `,` or `}`")));
+        assert_eq!(error(""), (Some(0), String::from("expected a JSON value, found end of input")));
+        assert_eq!(error("{\"test\": @}"), (Some(9), String::from("unexpected character `@`")));
+    }
+}
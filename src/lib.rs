@@ -1,10 +1,23 @@
 #![crate_type="dylib"]
 #![feature(plugin_registrar, rustc_private)]
 
+mod attrs;
+mod cascade;
+mod config;
+mod deps;
+mod diff;
+mod events;
+mod exported;
 mod filter;
+mod hollow;
+mod json;
+mod log;
+mod parse;
+mod prune;
+mod summary;
+mod toml;
+mod variants;
 
-#[macro_use]
-extern crate nom;
 extern crate regex;
 extern crate syntax;
 extern crate syntax_pos;
@@ -14,58 +27,741 @@ extern crate rustc_plugin;
 use syntax::feature_gate::AttributeType;
 use syntax::symbol::Symbol;
 use syntax::ext::quote::rt::Span;
-use syntax::ast::{MetaItem, Item, ItemKind, NodeId, Visibility};
+use syntax::ast::{Attribute, Block, Constness, ExprKind, ImplItem, ImplItemKind, LitKind, MetaItem,
+                  Item, ItemKind, Mac, Mac_, NestedMetaItemKind, NodeId, Path, StmtKind, StrStyle,
+                  TraitItem, TraitItemKind, Visibility};
+use syntax::codemap::respan;
 use syntax::ext::base::{ExtCtxt, Annotatable};
 use syntax::ext::base::SyntaxExtension;
+use syntax::ext::build::AstBuilder;
+use syntax::fold::Folder;
+use syntax::print::pprust;
 use syntax::ptr::P;
+use syntax::parse::token::{Lit, Token};
+use syntax::tokenstream::{TokenStream, TokenTree};
 use syntax_pos::hygiene::SyntaxContext;
 use syntax_pos::BytePos;
 use syntax_pos::symbol::Ident;
 use rustc_plugin::Registry;
 
-use filter::Filter;
+use filter::{AlwaysFilter, Context, Filter, Node, Refusal, Source};
+use parse::ParseError;
+
+use regex::Regex;
+
+use std::collections::HashSet;
+use std::env;
+use std::ffi::OsStr;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+// When set, the filter is validated and printed in normalized form, but nothing is removed.
+const CHECK_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_CHECK";
+// When set, every node which would be removed is reported, but the crate is left untouched.
+const DRY_RUN_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_DRY_RUN";
+// When set, a warning is emitted for every node removed.
+const WARN_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_WARN";
+// When set, it is an error for the filter from any source to match nothing, since that usually
+// means it's wrong (e.g., a misspelled name in a regex).
+const STRICT_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_STRICT";
+// Selects the mode, overriding the plugin's arguments.
+const MODE_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_MODE";
+// Selects what is done with the nodes the filter removes (see Action).
+const ACTION_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_ACTION";
+// Selects when the filter is applied (see Phase).
+const PHASE_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_PHASE";
+// A regex selecting attributes to strip from the nodes which are kept, by their paths (e.g.,
+// `^tracing::` or `^instrument$`), overriding the plugin's arguments.
+const STRIP_ATTRS_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_STRIP_ATTRS";
+// The path of a file to append a line to for every node removed (see write_report).
+const REPORT_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_REPORT";
+// When set, removed modules are replaced by empty ones, so that paths naming the modules
+// themselves (e.g., in `use` declarations and re-exports) still resolve.
+const MOD_SHELLS_ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_MOD_SHELLS";
+
+// How the filter is applied.
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    // Remove the nodes the filter matches.
+    Remove,
+    // Keep only the nodes the filter matches, along with the modules containing them.
+    Keep,
+    // Validate the filter and print it in normalized form, like RUST_DISABLE_CODE_CHECK.
+    Check,
+    // Remove the nodes the filter matches, and then stub out every function which remains (see
+    // hollow).
+    Hollow,
+}
+
+impl Mode {
+    fn from_name(s: &str) -> Option<Mode> {
+        match s {
+            "remove" => Some(Mode::Remove),
+            "keep" => Some(Mode::Keep),
+            "check" => Some(Mode::Check),
+            "hollow" => Some(Mode::Hollow),
+            _ => None,
+        }
+    }
+}
+
+// When the filter is applied, relative to macro expansion.
+#[derive(Clone, Copy, PartialEq)]
+enum Phase {
+    // Before expansion, so items generated by macros (including derives) are never filtered.
+    Pre,
+    // After expanding the crate's macros, so generated items are filtered too.
+    Post,
+}
+
+impl Phase {
+    fn from_name(s: &str) -> Option<Phase> {
+        match s {
+            "pre" => Some(Phase::Pre),
+            "post" => Some(Phase::Post),
+            _ => None,
+        }
+    }
+}
+
+// What is done with the nodes the filter removes.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    // Delete them.
+    Remove,
+    // Keep the signatures of functions, but replace their bodies with `unimplemented!()`. Other
+    // nodes are deleted.
+    Stub,
+    // Mark them with `#[cfg(any())]`, leaving the compiler to strip them, for the sake of tools
+    // which expect them to still exist.
+    Cfg,
+    // Replace public functions with deprecated shims which panic with a message naming the
+    // filter, so downstream users find out what happened. Other nodes are deleted.
+    Shim,
+}
+
+impl Action {
+    fn from_name(s: &str) -> Option<Action> {
+        match s {
+            "remove" => Some(Action::Remove),
+            "stub" => Some(Action::Stub),
+            "cfg" => Some(Action::Cfg),
+            "shim" => Some(Action::Shim),
+            _ => None,
+        }
+    }
+
+    // Returns the name the action is selected by (the inverse of from_name).
+    fn name(&self) -> &'static str {
+        match self {
+            &Action::Remove => "remove",
+            &Action::Stub => "stub",
+            &Action::Cfg => "cfg",
+            &Action::Shim => "shim",
+        }
+    }
+
+    // Returns the verb describing the action (e.g., "stub out" for `would stub out fn foo`).
+    fn verb(&self) -> &'static str {
+        match self {
+            &Action::Remove => "remove",
+            &Action::Stub => "stub out",
+            &Action::Cfg => "cfg out",
+            &Action::Shim => "deprecate",
+        }
+    }
+
+    // Returns the past tense of verb() (e.g., "stubbed out" for `stubbed out fn foo`).
+    fn past_tense(&self) -> &'static str {
+        match self {
+            &Action::Remove => "removed",
+            &Action::Stub => "stubbed out",
+            &Action::Cfg => "cfged out",
+            &Action::Shim => "deprecated",
+        }
+    }
+
+    // Returns true if a node the action was applied to still exists, and can still be referred to.
+    fn replaces(&self) -> bool {
+        match self {
+            &Action::Stub | &Action::Shim => true,
+            &Action::Remove | &Action::Cfg => false,
+        }
+    }
+}
+
+// How delete_item carries out the filter's decisions.
+#[derive(Clone, Copy)]
+struct Actions<'a> {
+    // Whether the filter selects the nodes to keep rather than those to remove.
+    keep: bool,
+    action: Action,
+    // The fully qualified paths of the nodes which must not be removed, whatever the filter says.
+    preserved: &'a HashSet<String>,
+    // Where the filter came from, for the messages of deprecation shims.
+    origin: &'a str,
+}
+
+// Settings given as arguments to the plugin itself (`#![plugin(disable_code(filter = "..."))]`).
+#[derive(Default)]
+struct PluginArgs {
+    filter: Option<String>,
+    mode: Option<Mode>,
+    strip_attrs: Option<String>,
+}
 
 fn modify_ast(cx: &mut ExtCtxt,
               span: Span,
               ast: &MetaItem,
-              annotatable: Annotatable)
+              annotatable: Annotatable,
+              args: &PluginArgs)
               -> Annotatable {
+    // The crate root is the only item without a name.
+    let misplaced = match annotatable {
+        Annotatable::Item(ref item) if item.ident.name.as_str() != "" => {
+            Some(Node::Item(item).kind_name())
+        }
+        _ => None,
+    };
+    if let Some(kind) = misplaced {
+        let message = format!("#[disable_code] only applies to the crate root, so it has no \
+                               effect on this {}",
+                              kind);
+        cx.struct_span_warn(span, &message)
+            .help("put #![disable_code] at the top of the crate root to filter the whole crate, \
+                   or use #[disable_code::filter(\"...\")] to give a module its own filter")
+            .emit();
+        return strip_plugin_attrs(annotatable);
+    }
+    if let Err(level) = log::init() {
+        cx.span_err(span, &format!("invalid {}: {}", log::ENV_VAR_NAME, level));
+        return annotatable;
+    }
+    // The environment takes precedence over the attribute, which takes precedence over the
+    // plugin's arguments, which take precedence over the configuration file.
+    let config = match config::load(&cx.ecfg.crate_name) {
+        Ok(config) => config,
+        Err(err) => {
+            cx.span_err(span, &format!("invalid {}: {}", config::FILE_NAME, err));
+            config::Config::default()
+        }
+    };
+    let rules = config.rules;
+    let default = attr_filter(cx, ast)
+        .map(|expr| Source::new("the disable_code attribute", expr))
+        .or(args.filter.clone().map(|expr| Source::new("the plugin arguments", expr)))
+        .or(config.filter.map(|expr| if rules {
+                                  Source::generated(config::FILE_NAME, expr)
+                              } else {
+                                  Source::new(config::FILE_NAME, expr)
+                              }));
+    let (sources, warnings) = filter::sources(default, &cx.ecfg.crate_name, is_release(cx));
+    for warning in warnings {
+        cx.span_warn(span, &warning);
+    }
+    let mode = match env::var(MODE_ENV_VAR_NAME) {
+        Ok(mode) => {
+            match Mode::from_name(&mode) {
+                Some(mode) => mode,
+                None => {
+                    cx.span_err(span, &format!("invalid {}: {}", MODE_ENV_VAR_NAME, mode));
+                    return annotatable;
+                }
+            }
+        }
+        Err(_) => args.mode.or(config.mode).unwrap_or(Mode::Remove),
+    };
+    let action = match env::var(ACTION_ENV_VAR_NAME) {
+        Ok(action) => {
+            match Action::from_name(&action) {
+                Some(action) => action,
+                None => {
+                    cx.span_err(span, &format!("invalid {}: {}", ACTION_ENV_VAR_NAME, action));
+                    return annotatable;
+                }
+            }
+        }
+        Err(_) => config.action.unwrap_or(Action::Remove),
+    };
+    let phase = match env::var(PHASE_ENV_VAR_NAME) {
+        Ok(phase) => {
+            match Phase::from_name(&phase) {
+                Some(phase) => phase,
+                None => {
+                    cx.span_err(span, &format!("invalid {}: {}", PHASE_ENV_VAR_NAME, phase));
+                    return annotatable;
+                }
+            }
+        }
+        Err(_) => Phase::Pre,
+    };
+    let strip_attrs = match env::var(STRIP_ATTRS_ENV_VAR_NAME).ok().or(args.strip_attrs.clone()) {
+        Some(re) => {
+            match Regex::new(&re) {
+                Ok(re) => Some(re),
+                Err(err) => {
+                    cx.span_err(span, &format!("invalid attribute regex {:?}: {}", re, err));
+                    return annotatable;
+                }
+            }
+        }
+        None => None,
+    };
+    if mode == Mode::Check || env_flag(CHECK_ENV_VAR_NAME) {
+        check_filter(cx, span, &sources);
+        // the plugin's own attributes are still stripped
+        return strip_plugin_attrs(annotatable);
+    }
+
     if let Annotatable::Item(item) = annotatable {
-        let mut it = item.unwrap();
-        // We should never be filtering out the root module
-        assert!(!delete_item(filter::env_to_filter().as_ref(), &mut it));
+        let keep = mode == Mode::Keep;
+        let (filter, counts) = match filter::to_filter(&sources, keep) {
+            Ok(result) => result,
+            Err(errs) => {
+                report_errors(cx, span, &errs);
+                return Annotatable::Item(item);
+            }
+        };
+        let filter = Rc::new(filter);
+        let it = match phase {
+            Phase::Pre => item.unwrap(),
+            // The expanded crate is left for the compiler, which has nothing left to expand.
+            Phase::Post => {
+                cx.monotonic_expander()
+                    .fold_item(item)
+                    .expect_one("expanding the crate should produce a single item")
+                    .unwrap()
+            }
+        };
+        let original = if env_flag(DRY_RUN_ENV_VAR_NAME) {
+            Some(it.clone())
+        } else {
+            None
+        };
+        let origin = sources.iter().map(|s| s.origin.as_str()).collect::<Vec<_>>().join(", ");
+        let visited = if summary::enabled() {
+            summary::count_nodes(&it)
+        } else {
+            0
+        };
+        let (mut it, mut removals) = apply_filter(cx, &filter, keep, action, &origin, it);
+        if mode == Mode::Hollow {
+            hollow::hollow(cx, &mut it, &mut removals);
+        }
+        if summary::enabled() {
+            summary::report(cx, span, visited, &removals);
+        }
+        if env_flag(STRICT_ENV_VAR_NAME) {
+            for count in counts.iter().filter(|count| count.get() == 0) {
+                cx.span_err(span,
+                            &format!("filter from {} matched nothing ({} is set)",
+                                     count.source.origin,
+                                     STRICT_ENV_VAR_NAME));
+            }
+        }
+        variants::check_patterns(cx, &it, &removals);
+        let mut stripped = Vec::new();
+        if let Some(ref re) = strip_attrs {
+            attrs::strip_where(&mut it, |attr| {
+                let path = pprust::path_to_string(&attr.path);
+                let matched = re.is_match(&path);
+                if matched {
+                    stripped.push((attr.span, path));
+                }
+                matched
+            });
+        }
+        // written in dry runs as well, since that's when a reviewer is most likely to want it
+        if let Some(path) = env::var_os(diff::ENV_VAR_NAME) {
+            if let Err(err) = diff::write_diff(&path, cx.codemap(), &removals) {
+                cx.span_err(span,
+                            &format!("could not write {} ({}): {}",
+                                     diff::ENV_VAR_NAME,
+                                     path.to_string_lossy(),
+                                     err));
+            }
+        }
+        if let Some(mut original) = original {
+            report_dry_run(cx, span, &removals, &stripped);
+            attrs::strip(&mut original);
+            return Annotatable::Item(P(original));
+        }
+        if env_flag(WARN_ENV_VAR_NAME) {
+            for removal in removals.iter() {
+                cx.span_warn(removal.span,
+                             &format!("{} {} `{}`",
+                                      removal.action.past_tense(),
+                                      removal.kind,
+                                      removal.path));
+            }
+        }
+        if prune::enabled() {
+            if let ItemKind::Mod(ref mut md) = it.node {
+                prune::prune_uses(md, &removals);
+            }
+        }
+        if let Some(path) = env::var_os(REPORT_ENV_VAR_NAME) {
+            if let Err(err) = write_report(&path, &cx.ecfg.crate_name, &removals) {
+                cx.span_err(span,
+                            &format!("could not write {} ({}): {}",
+                                     REPORT_ENV_VAR_NAME,
+                                     path.to_string_lossy(),
+                                     err));
+            }
+        }
+        if let Some(path) = env::var_os(events::ENV_VAR_NAME) {
+            let result = events::write_events(&path,
+                                              cx.codemap(),
+                                              &cx.ecfg.crate_name,
+                                              &removals);
+            if let Err(err) = result {
+                cx.span_err(span,
+                            &format!("could not write {} ({}): {}",
+                                     events::ENV_VAR_NAME,
+                                     path.to_string_lossy(),
+                                     err));
+            }
+        }
+        attrs::strip(&mut it);
         Annotatable::Item(P(it))
     } else {
-        // TODO: Emit warning about non-crate attribute
+        // the attribute is on a trait or impl item, which it can't filter
+        cx.span_warn(span,
+                     "#[disable_code] has no effect on trait and impl items; it must be applied \
+                      to the crate root as #![disable_code]");
         annotatable
     }
 }
 
+// Removes the plugin's attributes from an item which isn't being filtered, since the compiler
+// would reject them.
+fn strip_plugin_attrs(annotatable: Annotatable) -> Annotatable {
+    match annotatable {
+        Annotatable::Item(item) => {
+            Annotatable::Item(item.map(|mut it| {
+                                           attrs::strip(&mut it);
+                                           it
+                                       }))
+        }
+        annotatable => annotatable,
+    }
+}
+
+// Applies the filter to the crate, and returns the result along with a record of every node
+// removed. If dependencies are being preserved, this is repeated until no removed item is still
+// referred to. Protected nodes which the filter matched are reported as errors.
+fn apply_filter(cx: &ExtCtxt,
+                filter: &Rc<Box<Filter>>,
+                keep: bool,
+                action: Action,
+                origin: &str,
+                root: Item)
+                -> (Item, Vec<Removal>) {
+    // the crate root is filtered as usual
+    if let Some(attr) = root.attrs.iter().find(|attr| attrs::is_attr(attr, "remove")) {
+        cx.span_err(attr.span, "#[disable_code::remove] can't remove the crate root");
+    }
+    if !deps::enabled() {
+        let mut root = root;
+        let actions = Actions {
+            keep: keep,
+            action: action,
+            preserved: &HashSet::new(),
+            origin: origin,
+        };
+        let (removals, refusals) = filter_crate(cx, filter, actions, &mut root);
+        report_refusals(cx, &refusals);
+        return (root, removals);
+    }
+
+    let mut preserved = HashSet::new();
+    loop {
+        let mut it = root.clone();
+        let (removals, refusals) = filter_crate(cx,
+                                                filter,
+                                                Actions {
+                                                    keep: keep,
+                                                    action: action,
+                                                    preserved: &preserved,
+                                                    origin: origin,
+                                                },
+                                                &mut it);
+        let deps: Vec<_> = deps::dependencies(&it, &removals)
+            .into_iter()
+            .filter(|dep| !preserved.contains(&dep.path))
+            .collect();
+        if deps.is_empty() {
+            report_refusals(cx, &refusals);
+            return (it, removals);
+        }
+        for dep in deps {
+            let note = format!("keeping `{}` because {} refers to it", dep.path, dep.referrer);
+            cx.parse_sess.span_diagnostic.span_note_without_error(dep.span, &note);
+            preserved.insert(dep.path);
+        }
+    }
+}
+
+// Makes a single pass over the crate, removing the nodes selected by the filter along with those
+// which depend on them.
+fn filter_crate(cx: &ExtCtxt,
+                filter: &Rc<Box<Filter>>,
+                actions: Actions,
+                root: &mut Item)
+                -> (Vec<Removal>, Vec<Refusal>) {
+    let mut removals = Vec::new();
+    let mut ctx = Context::new(cx, root);
+    // We should never be filtering out the root module
+    assert!(!delete_item(cx, filter, actions, &mut ctx, &mut removals, root));
+    cascade::cascade(cx, &mut Context::new(cx, root), actions.action, root, &mut removals);
+    (removals, ctx.take_refusals())
+}
+
+// Returns the filter expression given in the attribute (`#![disable_code(filter = "...")]`), if
+// any. Unrecognized arguments are reported as errors.
+fn attr_filter(cx: &ExtCtxt, ast: &MetaItem) -> Option<String> {
+    let mut filter = None;
+    for nested in ast.meta_item_list().unwrap_or(&[]) {
+        match nested.value_str() {
+            Some(value) if nested.check_name("filter") => {
+                filter = Some(String::from(&value.as_str() as &str));
+            }
+            _ => cx.span_err(nested.span, "unrecognized argument to disable_code"),
+        }
+    }
+    filter
+}
+
+// Returns true if the crate is being compiled without debug assertions, as it is by default in
+// cargo's release profile (the PROFILE variable is only set for build scripts).
+fn is_release(cx: &ExtCtxt) -> bool {
+    !cx.parse_sess.config.iter().any(|&(name, _)| name.as_str() == "debug_assertions")
+}
+
+// Parses the plugin's arguments. Unrecognized arguments are reported as errors.
+fn plugin_args(reg: &Registry) -> PluginArgs {
+    let mut args = PluginArgs::default();
+    for nested in reg.args() {
+        match nested.value_str() {
+            Some(value) if nested.check_name("filter") => {
+                args.filter = Some(String::from(&value.as_str() as &str));
+            }
+            Some(value) if nested.check_name("strip_attrs") => {
+                args.strip_attrs = Some(String::from(&value.as_str() as &str));
+            }
+            Some(value) if nested.check_name("mode") => {
+                let mode: &str = &value.as_str();
+                match Mode::from_name(mode) {
+                    Some(mode) => args.mode = Some(mode),
+                    None => reg.sess.span_err(nested.span, &format!("unrecognized mode: {}", mode)),
+                }
+            }
+            _ => reg.sess.span_err(nested.span, "unrecognized argument to disable_code plugin"),
+        }
+    }
+    args
+}
+
+// Reports whether the filters are valid, along with their normalized forms.
+fn check_filter(cx: &ExtCtxt, span: Span, sources: &[Source]) {
+    let diagnostic = &cx.parse_sess.span_diagnostic;
+    match filter::check(sources) {
+        Ok(ref normalized) if normalized.is_empty() => {
+            diagnostic.span_note_without_error(span, "no filter is set")
+        }
+        Ok(normalized) => {
+            for (source, s) in normalized {
+                diagnostic.span_note_without_error(span,
+                                                   &format!("filter from {} is valid: {}",
+                                                            source.origin,
+                                                            s))
+            }
+        }
+        Err(errs) => report_errors(cx, span, &errs),
+    }
+}
+
+// Reports the nodes which would have been removed, and the attributes (given by their spans and
+// paths) which would have been stripped.
+fn report_dry_run(cx: &ExtCtxt, span: Span, removals: &[Removal], stripped: &[(Span, String)]) {
+    let diagnostic = &cx.parse_sess.span_diagnostic;
+    for removal in removals {
+        diagnostic.span_note_without_error(removal.span,
+                                           &format!("would {} {} `{}`",
+                                                    removal.action.verb(),
+                                                    removal.kind,
+                                                    removal.path));
+    }
+    for &(span, ref path) in stripped {
+        diagnostic.span_note_without_error(span, &format!("would strip attribute `#[{}]`", path));
+    }
+    if removals.is_empty() && stripped.is_empty() {
+        diagnostic.span_note_without_error(span, "the filter would not remove anything");
+    }
+}
+
+// Appends a line to the file at path for every removed node, giving its kind and its fully
+// qualified path, starting with the crate's name (e.g., `fn my_crate::foo::bar`). A node which was
+// not deleted outright is followed by what was done with it (e.g., `fn my_crate::foo (stub out)`).
+fn write_report(path: &OsStr, crate_name: &str, removals: &[Removal]) -> io::Result<()> {
+    let mut report = String::new();
+    for removal in removals {
+        report.push_str(&format!("{} {}::{}", removal.kind, crate_name, removal.path));
+        if removal.action != Action::Remove {
+            report.push_str(&format!(" ({})", removal.action.verb()));
+        }
+        report.push('\n');
+    }
+    // a single write, so that lines from crates compiled in parallel aren't interleaved
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(report.as_bytes())
+}
+
+fn report_refusals(cx: &ExtCtxt, refusals: &[Refusal]) {
+    for refusal in refusals {
+        let protected = match refusal.contains {
+            Some((ref description, _)) => {
+                format!("which contains {}, which is {}", description, refusal.reason)
+            }
+            None => format!("which is {}", refusal.reason),
+        };
+        let message = format!("refusing to remove {} `{}`, {}, although the filter `{}` from {} \
+                               matches it",
+                              refusal.kind,
+                              refusal.path,
+                              protected,
+                              refusal.filter,
+                              refusal.origin);
+        let mut diag = cx.struct_span_err(refusal.span, &message);
+        if let Some((ref description, span)) = refusal.contains {
+            diag.span_note(span, &format!("{} is declared here", description));
+        }
+        diag.help("wrap the filter in unsafe_allow_exports() to allow this").emit();
+    }
+}
+
+fn report_errors(cx: &ExtCtxt, span: Span, errs: &[(&Source, ParseError)]) {
+    for &(source, ref err) in errs {
+        let message = format!("invalid filter in {}: {}", source.origin, err);
+        let mut diag = cx.struct_span_err(span, &message);
+        if let Some(offset) = err.offset {
+            // a position in an expression the user didn't write wouldn't mean anything to them
+            if source.verbatim && offset <= source.expr.len() &&
+               source.expr.is_char_boundary(offset) {
+                diag.note(&point_at(&source.expr, offset));
+            }
+        }
+        diag.emit();
+    }
+}
+
+// Renders the line of an expression containing the given byte offset, with a caret under the
+// character at the offset below it, like rustc does for errors in format strings.
+fn point_at(expr: &str, offset: usize) -> String {
+    let start = expr[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = expr[offset..].find('\n').map_or(expr.len(), |i| offset + i);
+    // tabs are kept so that the caret lines up however wide they're displayed
+    let indent: String = expr[start..offset]
+        .chars()
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    let line = expr[..start].matches('\n').count() + 1;
+    if start == 0 && end == expr.len() {
+        format!("{}\n{}^", &expr[start..end], indent)
+    } else {
+        format!("line {}:\n{}\n{}^", line, &expr[start..end], indent)
+    }
+}
+
+// A record of a node which was removed from the crate.
+pub struct Removal {
+    // The fully qualified path of the module which contained the node (empty for the root module).
+    pub module: String,
+    // The fully qualified path of the node itself.
+    pub path: String,
+    pub kind: &'static str,
+    pub span: Span,
+    // The identifiers referenced by the node.
+    pub refs: HashSet<String>,
+    // What was done with the node (which is never Stub or Shim unless the node was a function).
+    pub action: Action,
+    // The origin of the filter which matched the node, unless the node was removed for some other
+    // reason (e.g., by cascade, or in keep mode).
+    pub matched_by: Option<String>,
+}
+
+impl Removal {
+    fn new(ctx: &Context, node: &Node, action: Action) -> Removal {
+        Removal {
+            module: ctx.mod_path(),
+            path: ctx.node_path(node),
+            kind: node.kind_name(),
+            span: node.span(),
+            refs: prune::node_refs(node),
+            action: action,
+            matched_by: ctx.take_matched_by(node),
+        }
+    }
+}
+
 // Deletes any items that should be deleted, and returns true if its argument should be deleted.
-fn delete_item(filter: &Filter, item: &mut Item) -> bool {
-    if filter.apply(item) {
+// Every deleted item (other than the argument itself) is recorded in removals.
+//
+// If actions.keep is true, the filter selects the nodes to keep instead: those it matches are kept
+// whole, and modules (and extern and impl blocks) are kept if anything inside of them is. Nodes
+// inside of the argument are only deleted if actions.action is Remove, but are recorded either way.
+fn delete_item(cx: &ExtCtxt,
+               filter: &Rc<Box<Filter>>,
+               actions: Actions,
+               ctx: &mut Context,
+               removals: &mut Vec<Removal>,
+               item: &mut Item)
+               -> bool {
+    let keep = actions.keep;
+    // an item marked with #[disable_code::keep] is left alone, along with everything inside of it
+    if attrs::is_kept(&item.attrs) {
+        return false;
+    }
+    // and one marked with #[disable_code::remove] is removed whatever the filter says, except for
+    // the crate root (the only item without a name; see apply_filter)
+    if attrs::is_removed(&item.attrs) && item.ident.name.as_str() != "" {
         return true;
     }
+    // a preserved node is kept whole in keep mode, but is only exempt itself otherwise
+    let matched = if is_preserved(actions, ctx, &Node::Item(item)) {
+        keep
+    } else {
+        filter.apply(ctx, &Node::Item(item))
+    };
+    // In keep mode, the nodes marked for removal inside of an item that's kept whole are still
+    // removed, by keeping everything else inside of it. Likewise, in remove mode, the nodes marked
+    // to be kept inside of an item that's removed are still kept, by removing everything else.
+    let always;
+    let filter = if !matched {
+        filter
+    } else if (keep && contains_removed(item)) || (!keep && contains_kept(item)) {
+        always = Rc::new(AlwaysFilter::new());
+        &always
+    } else {
+        return !keep;
+    };
 
     match &mut item.node {
         &mut ItemKind::Mod(ref mut md) => {
+            // The root module has an empty name and is not part of item paths.
+            let name = item.ident.name.as_str();
+            let is_root = name == "";
+            if !is_root {
+                ctx.push_mod(&name);
+            }
+            let filter = &module_filter(cx, filter, keep, &item.attrs, item.span);
+
             let mut to_delete = Vec::new();
             for i in 0..md.items.len() {
-                // We can't mutate the item directly because P (libsyntax's owned pointer type)
-                // doesn't allow mutation of its referent. We also can't take ownership of its
-                // referent because that would consitute moving a borrowed value (since we only
-                // have a mutable reference). Thus, we do this silly song and dance of creating a
-                // dummy P<Item>, swap it with the item, do what we need to on the dummy, and then
-                // swap it back.
-
-                let mut item = md.items.get_mut(i).unwrap();
-                let mut dummy = P(dummy_item());
-                use std::mem::swap;
-                swap(item, &mut dummy);
-
-                let mut it = dummy.unwrap();
-                let delete = delete_item(filter, &mut it);
-                swap(item, &mut P(it));
+                let delete = with_item_mut(md.items.get_mut(i).unwrap(), |it| {
+                    filter_child(cx, filter, actions, ctx, removals, it)
+                });
 
                 if delete {
                     to_delete.push(i);
@@ -78,12 +774,516 @@ fn delete_item(filter: &Filter, item: &mut Item) -> bool {
                 offset += 1;
             }
 
+            if !is_root {
+                ctx.pop_mod();
+            }
+            keep && !is_root && md.items.is_empty()
+        }
+        &mut ItemKind::ForeignMod(ref mut fm) => {
+            let mut to_delete = Vec::new();
+            for item in fm.items.iter_mut() {
+                let delete = {
+                    let node = Node::ForeignItem(item);
+                    !is_exempt(actions, ctx, &node) &&
+                    (attrs::is_removed(node.attrs()) || filter.apply(ctx, &node) != keep)
+                };
+                if delete {
+                    let action = if actions.action == Action::Cfg {
+                        Action::Cfg
+                    } else {
+                        Action::Remove
+                    };
+                    removals.push(Removal::new(ctx, &Node::ForeignItem(item), action));
+                    if actions.action == Action::Cfg {
+                        item.attrs.push(cfg_false(cx, item.span));
+                    }
+                }
+                to_delete.push(delete && actions.action != Action::Cfg);
+            }
+            let mut to_delete = to_delete.into_iter();
+            fm.items.retain(|_| !to_delete.next().unwrap());
+            keep && fm.items.is_empty()
+        }
+        &mut ItemKind::Impl(_, _, _, _, ref trait_ref, ref self_ty, ref mut items) => {
+            ctx.push_impl(self_ty, trait_ref.is_some());
+            let mut to_delete = Vec::new();
+            for item in items.iter_mut() {
+                let delete = {
+                    let node = Node::ImplItem(item);
+                    !is_exempt(actions, ctx, &node) &&
+                    (attrs::is_removed(node.attrs()) || filter.apply(ctx, &node) != keep)
+                };
+                if delete {
+                    let required = match *trait_ref {
+                        Some(ref trait_ref) => ctx.must_define(trait_ref, item.ident),
+                        None => false,
+                    };
+                    let mut removal = Removal::new(ctx, &Node::ImplItem(item), actions.action);
+                    let message = shim_message(actions, &removal.path);
+                    let action = match actions.action {
+                        Action::Stub if stub_method(cx, item, None) => Some(Action::Stub),
+                        Action::Shim if is_pub(&item.vis) &&
+                                        stub_method(cx, item, Some(&message)) => {
+                            Some(Action::Shim)
+                        }
+                        // An impl which doesn't define a required item doesn't compile, so a
+                        // required method is stubbed out instead, and anything else is kept.
+                        _ if required => {
+                            if stub_method(cx, item, None) {
+                                let note = format!("stubbing out `{}` instead, since the trait \
+                                                    requires it",
+                                                   removal.path);
+                                cx.parse_sess
+                                    .span_diagnostic
+                                    .span_note_without_error(item.span, &note);
+                                Some(Action::Stub)
+                            } else {
+                                None
+                            }
+                        }
+                        Action::Cfg => {
+                            item.attrs.push(cfg_false(cx, item.span));
+                            Some(Action::Cfg)
+                        }
+                        _ => Some(Action::Remove),
+                    };
+                    match action {
+                        Some(action) => {
+                            removal.action = action;
+                            removals.push(removal);
+                            to_delete.push(action == Action::Remove);
+                        }
+                        None => {
+                            cx.span_err(item.span,
+                                        &format!("cannot remove {} `{}`, which the trait requires",
+                                                 removal.kind,
+                                                 removal.path));
+                            to_delete.push(false);
+                        }
+                    }
+                } else {
+                    to_delete.push(false);
+                }
+            }
+            let mut to_delete = to_delete.into_iter();
+            items.retain(|_| !to_delete.next().unwrap());
+            ctx.pop_owner();
+            keep && items.is_empty()
+        }
+        &mut ItemKind::Trait(_, _, _, ref mut items) => {
+            ctx.push_owner(&item.ident.name.as_str());
+            // in keep mode, the trait is only kept if the filter selects something inside of it
+            let mut matched_any = false;
+            let mut to_delete = Vec::new();
+            for item in items.iter_mut() {
+                let delete = {
+                    let node = Node::TraitItem(item);
+                    let matched = if attrs::is_kept(&item.attrs) {
+                        keep
+                    } else if attrs::is_removed(&item.attrs) {
+                        !keep
+                    } else if is_preserved(actions, ctx, &node) {
+                        keep
+                    } else {
+                        filter.apply(ctx, &node)
+                    };
+                    matched_any |= matched;
+                    // Items without a default can't be removed without breaking the trait's
+                    // implementations, which define them.
+                    matched != keep && has_default(item)
+                };
+                if delete {
+                    let mut removal = Removal::new(ctx, &Node::TraitItem(item), actions.action);
+                    let delete = match actions.action {
+                        Action::Remove => true,
+                        Action::Stub if stub_trait_method(cx, item) => false,
+                        // trait items have no visibility of their own, and so are never shimmed
+                        Action::Stub | Action::Shim => {
+                            removal.action = Action::Remove;
+                            true
+                        }
+                        Action::Cfg => {
+                            item.attrs.push(cfg_false(cx, item.span));
+                            false
+                        }
+                    };
+                    removals.push(removal);
+                    to_delete.push(delete);
+                } else {
+                    to_delete.push(false);
+                }
+            }
+            let mut to_delete = to_delete.into_iter();
+            items.retain(|_| !to_delete.next().unwrap());
+            ctx.pop_owner();
+            keep && !matched_any
+        }
+        &mut ItemKind::Fn(_, _, _, _, _, ref mut body) => {
+            // in keep mode, functions are only kept whole
+            if keep {
+                return true;
+            }
+            ctx.push_fn(&item.ident.name.as_str());
+            with_block_mut(cx, body, |block| {
+                let mut to_delete = Vec::new();
+                for stmt in block.stmts.iter_mut() {
+                    let delete = match stmt.node {
+                        StmtKind::Item(ref mut it) => {
+                            with_item_mut(it, |it| {
+                                filter_child(cx, filter, actions, ctx, removals, it)
+                            })
+                        }
+                        _ => false,
+                    };
+                    to_delete.push(delete);
+                }
+                let mut to_delete = to_delete.into_iter();
+                block.stmts.retain(|_| !to_delete.next().unwrap());
+            });
+            ctx.pop_fn();
+            false
+        }
+        &mut ItemKind::Enum(ref mut def, _) => {
+            // in keep mode, enums are only kept whole (which a kept variant requires)
+            if keep {
+                return !def.variants.iter().any(|variant| attrs::is_kept(&variant.node.attrs));
+            }
+            ctx.push_owner(&item.ident.name.as_str());
+            let mut to_delete = Vec::new();
+            for variant in def.variants.iter_mut() {
+                let delete = {
+                    let node = Node::Variant(variant);
+                    !is_exempt(actions, ctx, &node) &&
+                    (attrs::is_removed(node.attrs()) || filter.apply(ctx, &node))
+                };
+                if delete {
+                    let action = if actions.action == Action::Cfg {
+                        Action::Cfg
+                    } else {
+                        Action::Remove
+                    };
+                    removals.push(Removal::new(ctx, &Node::Variant(variant), action));
+                    if actions.action == Action::Cfg {
+                        variant.node.attrs.push(cfg_false(cx, variant.span));
+                    }
+                }
+                to_delete.push(delete && actions.action != Action::Cfg);
+            }
+            let mut to_delete = to_delete.into_iter();
+            def.variants.retain(|_| !to_delete.next().unwrap());
+            ctx.pop_owner();
             false
         }
+        // Imports are never removed in keep mode, since the items they import may be kept (unused
+        // ones are left for prune). This includes the `extern crate std` and prelude import which
+        // the compiler injects.
+        &mut ItemKind::Use(_) | &mut ItemKind::ExternCrate(_) => false,
+        _ => keep,
+    }
+}
+
+// Applies delete_item to an item inside of a module or function body, and carries out the action
+// if the item is to be removed. Returns true if it should be deleted from its parent.
+fn filter_child(cx: &ExtCtxt,
+                filter: &Rc<Box<Filter>>,
+                actions: Actions,
+                ctx: &mut Context,
+                removals: &mut Vec<Removal>,
+                it: &mut Item)
+                -> bool {
+    if !delete_item(cx, filter, actions, ctx, removals, it) {
+        return false;
+    }
+    // The node is recorded before it's stubbed out so that prune can tell which identifiers its
+    // body used.
+    let mut removal = Removal::new(ctx, &Node::Item(it), actions.action);
+    let message = shim_message(actions, &removal.path);
+    let delete = match actions.action {
+        Action::Remove => true,
+        Action::Stub if stub_fn(cx, it, None) => false,
+        Action::Shim if is_pub(&it.vis) && stub_fn(cx, it, Some(&message)) => false,
+        Action::Stub | Action::Shim => {
+            removal.action = Action::Remove;
+            true
+        }
+        Action::Cfg => {
+            it.attrs.push(cfg_false(cx, it.span));
+            false
+        }
+    };
+    removals.push(removal);
+    if delete && env_flag(MOD_SHELLS_ENV_VAR_NAME) {
+        if let ItemKind::Mod(ref mut md) = it.node {
+            md.items.clear();
+            return false;
+        }
+    }
+    delete
+}
+
+fn is_preserved(actions: Actions, ctx: &Context, node: &Node) -> bool {
+    !actions.preserved.is_empty() && actions.preserved.contains(&ctx.node_path(node))
+}
+
+// Returns true if anything inside of the item is marked with #[disable_code::remove], not counting
+// the contents of function bodies and enums (which keep mode only keeps whole).
+fn contains_removed(item: &Item) -> bool {
+    match item.node {
+        ItemKind::Mod(ref md) => {
+            md.items.iter().any(|item| {
+                                    !attrs::is_kept(&item.attrs) &&
+                                    (attrs::is_removed(&item.attrs) || contains_removed(item))
+                                })
+        }
+        ItemKind::ForeignMod(ref fm) => fm.items.iter().any(|item| attrs::is_removed(&item.attrs)),
+        ItemKind::Impl(_, _, _, _, _, _, ref items) => {
+            items.iter().any(|item| attrs::is_removed(&item.attrs))
+        }
+        ItemKind::Trait(_, _, _, ref items) => {
+            items.iter().any(|item| attrs::is_removed(&item.attrs))
+        }
+        _ => false,
+    }
+}
+
+// Returns true if anything inside of the item, including the items declared in function bodies,
+// is marked with #[disable_code::keep] (the counterpart of contains_removed for remove mode).
+fn contains_kept(item: &Item) -> bool {
+    let kept = |item: &Item| {
+        !attrs::is_removed(&item.attrs) && (attrs::is_kept(&item.attrs) || contains_kept(item))
+    };
+    match item.node {
+        ItemKind::Mod(ref md) => md.items.iter().any(|item| kept(item)),
+        ItemKind::Fn(_, _, _, _, _, ref body) => {
+            body.stmts.iter().any(|stmt| match stmt.node {
+                                      StmtKind::Item(ref item) => kept(item),
+                                      _ => false,
+                                  })
+        }
+        ItemKind::ForeignMod(ref fm) => fm.items.iter().any(|item| attrs::is_kept(&item.attrs)),
+        ItemKind::Impl(_, _, _, _, _, _, ref items) => {
+            items.iter().any(|item| attrs::is_kept(&item.attrs))
+        }
+        ItemKind::Trait(_, _, _, ref items) => items.iter().any(|item| attrs::is_kept(&item.attrs)),
+        ItemKind::Enum(ref def, _) => {
+            def.variants.iter().any(|variant| attrs::is_kept(&variant.node.attrs))
+        }
         _ => false,
     }
 }
 
+// Returns true if a node inside of an item is exempt from filtering, either because it's preserved
+// or because it's marked with #[disable_code::keep].
+fn is_exempt(actions: Actions, ctx: &Context, node: &Node) -> bool {
+    attrs::is_kept(node.attrs()) || is_preserved(actions, ctx, node)
+}
+
+// Replaces the body of a function with `unimplemented!()`, and returns true, unless the item isn't
+// a function or is a const fn (which can't panic). If shim is given, the body panics with it as
+// the message instead, and the function is marked deprecated with it as the note.
+fn stub_fn(cx: &ExtCtxt, item: &mut Item, shim: Option<&str>) -> bool {
+    match item.node {
+        ItemKind::Fn(_, _, ref constness, _, _, ref mut body) => {
+            if constness.node == Constness::Const {
+                return false;
+            }
+            *body = stub_body(cx, item.span, shim);
+        }
+        _ => return false,
+    }
+    item.attrs.push(allow_unused_variables(cx, item.span));
+    if let Some(message) = shim {
+        item.attrs.push(deprecated(cx, item.span, message));
+    }
+    true
+}
+
+// Like stub_fn, but for methods and associated items.
+fn stub_method(cx: &ExtCtxt, item: &mut ImplItem, shim: Option<&str>) -> bool {
+    match item.node {
+        ImplItemKind::Method(ref sig, ref mut body) => {
+            if sig.constness.node == Constness::Const {
+                return false;
+            }
+            *body = stub_body(cx, item.span, shim);
+        }
+        _ => return false,
+    }
+    item.attrs.push(allow_unused_variables(cx, item.span));
+    if let Some(message) = shim {
+        item.attrs.push(deprecated(cx, item.span, message));
+    }
+    true
+}
+
+// Like stub_fn, but for the default implementations of trait methods.
+fn stub_trait_method(cx: &ExtCtxt, item: &mut TraitItem) -> bool {
+    match item.node {
+        TraitItemKind::Method(ref sig, Some(ref mut body)) => {
+            if sig.constness.node == Constness::Const {
+                return false;
+            }
+            *body = stub_body(cx, item.span, None);
+        }
+        _ => return false,
+    }
+    item.attrs.push(allow_unused_variables(cx, item.span));
+    true
+}
+
+// Returns true if a trait item has a default, and so need not be defined by implementations.
+pub fn has_default(item: &TraitItem) -> bool {
+    match item.node {
+        TraitItemKind::Const(_, ref default) => default.is_some(),
+        TraitItemKind::Method(_, ref body) => body.is_some(),
+        TraitItemKind::Type(_, ref default) => default.is_some(),
+        // the macro may expand to items without defaults
+        TraitItemKind::Macro(..) => false,
+    }
+}
+
+// Returns the block `{ unimplemented!() }`, or, given a shim message, `{ panic!("message") }`.
+fn stub_body(cx: &ExtCtxt, span: Span, shim: Option<&str>) -> P<Block> {
+    let (name, tts) = match shim {
+        Some(message) => {
+            // a string literal token holds the escaped form of the string
+            let quoted = format!("{:?}", message);
+            let lit = Lit::Str_(Symbol::intern(&quoted[1..quoted.len() - 1]));
+            ("panic", TokenStream::from(TokenTree::Token(span, Token::Literal(lit, None))))
+        }
+        None => ("unimplemented", TokenStream::empty()),
+    };
+    let mac = Mac {
+        node: Mac_ {
+            path: Path::from_ident(span, Ident::from_str(name)),
+            tts: tts.into(),
+        },
+        span: span,
+    };
+    cx.block_expr(cx.expr(span, ExprKind::Mac(mac)))
+}
+
+// Returns the panic message (and deprecation note) of the shim replacing the node with the given
+// path.
+fn shim_message(actions: Actions, path: &str) -> String {
+    format!("`{}` was removed by the disable_code filter from {}", path, actions.origin)
+}
+
+// Returns a `#[deprecated(note = "...")]` attribute.
+fn deprecated(cx: &ExtCtxt, span: Span, note: &str) -> Attribute {
+    let note = cx.meta_name_value(span,
+                                  Symbol::intern("note"),
+                                  LitKind::Str(Symbol::intern(note), StrStyle::Cooked));
+    let deprecated = cx.meta_list(span,
+                                  Symbol::intern("deprecated"),
+                                  vec![respan(span, NestedMetaItemKind::MetaItem(note))]);
+    cx.attribute(span, deprecated)
+}
+
+fn is_pub(vis: &Visibility) -> bool {
+    match vis {
+        &Visibility::Public => true,
+        _ => false,
+    }
+}
+
+// Returns an `#[allow(unused_variables)]` attribute, since the parameters of a stubbed out function
+// are no longer used.
+fn allow_unused_variables(cx: &ExtCtxt, span: Span) -> Attribute {
+    let lint = cx.meta_list_item_word(span, Symbol::intern("unused_variables"));
+    let allow = cx.meta_list(span, Symbol::intern("allow"), vec![lint]);
+    cx.attribute(span, allow)
+}
+
+// Returns a `#[cfg(any())]` attribute, which is never satisfied.
+fn cfg_false(cx: &ExtCtxt, span: Span) -> Attribute {
+    let any = cx.meta_list(span, Symbol::intern("any"), Vec::new());
+    let cfg = cx.meta_list(span,
+                           Symbol::intern("cfg"),
+                           vec![respan(span, NestedMetaItemKind::MetaItem(any))]);
+    cx.attribute(span, cfg)
+}
+
+// Returns true if attr is `#[cfg(any())]`.
+fn is_cfg_false(attr: &Attribute) -> bool {
+    if !attr.check_name("cfg") {
+        return false;
+    }
+    let list = match attr.meta_item_list() {
+        Some(list) if list.len() == 1 => list,
+        _ => return false,
+    };
+    match list[0].meta_item() {
+        Some(any) => any.check_name("any") && any.meta_item_list().map_or(false, |l| l.is_empty()),
+        None => false,
+    }
+}
+
+// Returns the filter to apply to the items in a module with the given attributes: the one given
+// by its `#[disable_code::filter]` attribute if it has one, or else the enclosing module's.
+fn module_filter(cx: &ExtCtxt,
+                 inherited: &Rc<Box<Filter>>,
+                 keep: bool,
+                 attrs: &[Attribute],
+                 span: Span)
+                 -> Rc<Box<Filter>> {
+    let exprs = attrs::str_args(attrs, "filter");
+    if exprs.is_empty() {
+        return inherited.clone();
+    }
+    if exprs.len() > 1 {
+        cx.span_err(span, "a module can only have one disable_code::filter expression");
+        return inherited.clone();
+    }
+    match filter::module_filter(&exprs[0], inherited.clone(), keep) {
+        Ok(filter) => Rc::new(filter),
+        Err(errs) => {
+            for err in errs {
+                cx.span_err(span, &format!("invalid disable_code::filter attribute: {}", err));
+            }
+            inherited.clone()
+        }
+    }
+}
+
+// Calls f on the item behind an owned pointer, allowing it to be mutated in place.
+//
+// We can't mutate the item directly because P (libsyntax's owned pointer type) doesn't allow
+// mutation of its referent. We also can't take ownership of its referent because that would
+// consitute moving a borrowed value (since we only have a mutable reference). Thus, we do this
+// silly song and dance of creating a dummy P<Item>, swap it with the item, do what we need to on
+// the dummy, and then swap it back.
+fn with_item_mut<R, F: FnOnce(&mut Item) -> R>(item: &mut P<Item>, f: F) -> R {
+    let mut dummy = P(dummy_item());
+    use std::mem::swap;
+    swap(item, &mut dummy);
+
+    let mut it = dummy.unwrap();
+    let ret = f(&mut it);
+    swap(item, &mut P(it));
+    ret
+}
+
+// Like with_item_mut, but for blocks.
+fn with_block_mut<R, F: FnOnce(&mut Block) -> R>(cx: &ExtCtxt, block: &mut P<Block>, f: F) -> R {
+    let mut dummy = cx.block(block.span, Vec::new());
+    use std::mem::swap;
+    swap(block, &mut dummy);
+
+    let mut b = dummy.unwrap();
+    let ret = f(&mut b);
+    swap(block, &mut P(b));
+    ret
+}
+
+// Returns true if the given environment variable is set to a value other than "" or "0".
+fn env_flag(name: &str) -> bool {
+    match env::var(name) {
+        Ok(val) => val != "" && val != "0",
+        Err(_) => false,
+    }
+}
+
 // Returns an arbitrary Item. It should not be used for anything other than temporarily taking the
 // place of other Items.
 fn dummy_item() -> Item {
@@ -104,6 +1304,13 @@ fn dummy_item() -> Item {
 #[plugin_registrar]
 pub fn plugin_registrar(reg: &mut Registry) {
     reg.register_attribute(String::from("disable_code"), AttributeType::CrateLevel);
+    for name in attrs::NAMES {
+        reg.register_attribute(attrs::registered_name(name), AttributeType::Whitelisted);
+    }
+    let args = plugin_args(reg);
+    let modifier = move |cx: &mut ExtCtxt, span: Span, ast: &MetaItem, annotatable: Annotatable| {
+        modify_ast(cx, span, ast, annotatable, &args)
+    };
     reg.register_syntax_extension(Symbol::intern("disable_code"),
-                                  SyntaxExtension::MultiModifier(Box::new(modify_ast)));
+                                  SyntaxExtension::MultiModifier(Box::new(modifier)));
 }
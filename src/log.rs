@@ -0,0 +1,66 @@
+// Diagnostic logging to stderr, for debugging filters. The level is selected by
+// RUST_DISABLE_CODE_LOG: `off` (the default), `info` for the parsed filter expressions, `debug` for
+// the decision made for every node, and `trace` for the result of every sub-filter as well.
+
+use std::cell::Cell;
+use std::env;
+use std::io::{self, Write};
+
+pub const ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_LOG";
+
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Off,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    fn from_name(s: &str) -> Option<Level> {
+        match s {
+            "off" | "" => Some(Level::Off),
+            "info" => Some(Level::Info),
+            "debug" => Some(Level::Debug),
+            "trace" => Some(Level::Trace),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            &Level::Off => "off",
+            &Level::Info => "info",
+            &Level::Debug => "debug",
+            &Level::Trace => "trace",
+        }
+    }
+}
+
+thread_local! {
+    static LEVEL: Cell<Level> = Cell::new(Level::Off);
+}
+
+// Sets the level from the environment, returning the variable's value if it's invalid.
+pub fn init() -> Result<(), String> {
+    let level = match env::var(ENV_VAR_NAME) {
+        Ok(value) => Level::from_name(&value).ok_or(value)?,
+        Err(_) => Level::Off,
+    };
+    LEVEL.with(|l| l.set(level));
+    Ok(())
+}
+
+// Returns true if messages at the given level are logged.
+pub fn enabled(level: Level) -> bool {
+    level != Level::Off && LEVEL.with(|l| level <= l.get())
+}
+
+// Logs a message at the given level. Callers which have to do work to construct the message should
+// check enabled first.
+pub fn log(level: Level, message: &str) {
+    if enabled(level) {
+        // there's nowhere else to report a failure to write to stderr
+        let _ = writeln!(io::stderr(), "disable_code [{}]: {}", level.name(), message);
+    }
+}
@@ -0,0 +1,624 @@
+// A lexer and recursive-descent parser for filter expressions.
+//
+// The grammar is:
+//
+//   expr     = and_expr ("||" and_expr)*
+//   and_expr = unary ("&&" unary)*
+//   unary    = "!" unary | primary
+//   primary  = "(" expr ")" | call | string | number
+//   call     = name "(" [expr ("," expr)*] ")"
+//
+// where names are a lowercase letter followed by any lowercase letters, digits, and underscores,
+// and whitespace and comments (a `#` followed by anything up to the end of the line) may appear
+// between any two tokens. Operators are desugared to calls (e.g., `a && !b` is `and(a, not(b))`),
+// as are the bare words listed in bare_word (e.g., `tests` is `test()`).
+//
+// The parser recovers from errors by skipping to the next `,` or `)`, so that every independent
+// error in an expression can be reported at once.
+
+use std::fmt;
+use std::iter;
+
+// An error in a filter expression.
+#[derive(Debug)]
+pub struct ParseError {
+    // The byte offset in the expression at which the error was detected, if known.
+    pub offset: Option<usize>,
+    pub message: String,
+}
+
+impl ParseError {
+    pub fn new<S: Into<String>>(message: S) -> ParseError {
+        ParseError {
+            offset: None,
+            message: message.into(),
+        }
+    }
+
+    // Attributes the error to the given offset unless it has already been attributed to a more
+    // precise one.
+    pub fn at(self, offset: usize) -> ParseError {
+        ParseError { offset: self.offset.or(Some(offset)), ..self }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.offset {
+            Some(offset) => write!(f, "at offset {}: {}", offset, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Expr {
+    Quote(String),
+    Num(u64),
+    Call(Call),
+}
+
+// Formats an expression in its normalized form: every operator written as a call, every string
+// as a quote with `"` and `\` escaped, and a single space after each comma.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Expr::Quote(ref s) => {
+                write!(f, "\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+            }
+            &Expr::Num(n) => write!(f, "{}", n),
+            &Expr::Call(ref call) => {
+                write!(f, "{}(", call.name)?;
+                for (i, arg) in call.args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Call {
+    pub name: String,
+    pub args: Vec<Expr>,
+    // The byte offset of the call in the expression.
+    pub offset: usize,
+}
+
+// Parses a filter expression, returning every error found if it is invalid.
+pub fn parse(input: &str) -> Result<Expr, Vec<ParseError>> {
+    let mut errors = Vec::new();
+    let tokens = lex(input, &mut errors);
+    let mut parser = Parser {
+        tokens: tokens,
+        pos: 0,
+        errors: errors,
+    };
+    let expr = parser.expr();
+    if parser.peek() != &Token::Eof {
+        let message = format!("unexpected {} after expression", parser.peek());
+        parser.error(message);
+    }
+
+    match expr {
+        Some(expr) if parser.errors.is_empty() => Ok(expr),
+        _ => Err(parser.errors),
+    }
+}
+
+// Returns true if s is a valid function name.
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some('a'...'z') => chars.all(is_name_char),
+        _ => false,
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    match c {
+        'a'...'z' | '0'...'9' | '_' => true,
+        _ => false,
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    Str(String),
+    Num(u64),
+    LParen,
+    RParen,
+    Comma,
+    AndAnd,
+    OrOr,
+    Bang,
+    Eof,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            &Token::Name(ref name) => write!(f, "`{}`", name),
+            &Token::Str(_) => write!(f, "string"),
+            &Token::Num(_) => write!(f, "number"),
+            &Token::LParen => write!(f, "`(`"),
+            &Token::RParen => write!(f, "`)`"),
+            &Token::Comma => write!(f, "`,`"),
+            &Token::AndAnd => write!(f, "`&&`"),
+            &Token::OrOr => write!(f, "`||`"),
+            &Token::Bang => write!(f, "`!`"),
+            &Token::Eof => write!(f, "end of input"),
+        }
+    }
+}
+
+// Splits the input into tokens, each paired with its byte offset. The last token is always Eof.
+// Characters which can't start a token are recorded in errors and skipped.
+fn lex(input: &str, errors: &mut Vec<ParseError>) -> Vec<(usize, Token)> {
+    let bytes = input.as_bytes();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &input[i..];
+        let next = bytes.get(i + 1).cloned();
+        let (len, token) = match bytes[i] {
+            b' ' | b'\t' | b'\r' | b'\n' | b'\x0c' => (1, None),
+            b'#' => (rest.find('\n').unwrap_or(rest.len()), None),
+            b'(' => (1, Some(Token::LParen)),
+            b')' => (1, Some(Token::RParen)),
+            b',' => (1, Some(Token::Comma)),
+            b'!' => (1, Some(Token::Bang)),
+            b'&' | b'|' => {
+                let token = if bytes[i] == b'&' { Token::AndAnd } else { Token::OrOr };
+                if next == Some(bytes[i]) {
+                    (2, Some(token))
+                } else {
+                    // treat a lone `&` or `|` as the operator so that parsing can continue
+                    errors.push(ParseError::new(format!("expected {}", token)).at(i));
+                    (1, Some(token))
+                }
+            }
+            b'"' => {
+                let (len, s) = lex_quote(rest);
+                if s.is_none() {
+                    errors.push(ParseError::new("unterminated string").at(i));
+                }
+                (len, Some(Token::Str(s.unwrap_or_default())))
+            }
+            // names can't contain `"` or `#`, so an `r` followed by either starts a raw string
+            b'r' if next == Some(b'"') || next == Some(b'#') => {
+                let (len, s) = lex_raw_quote(rest);
+                if s.is_none() {
+                    errors.push(ParseError::new("unterminated raw string").at(i));
+                }
+                (len, Some(Token::Str(s.unwrap_or_default())))
+            }
+            b'0'...b'9' => {
+                let digits = rest.split(|c| !('0' <= c && c <= '9')).next().unwrap();
+                let n = match digits.parse() {
+                    Ok(n) => n,
+                    Err(_) => {
+                        let message = format!("number is too large: {}", digits);
+                        errors.push(ParseError::new(message).at(i));
+                        0
+                    }
+                };
+                (digits.len(), Some(Token::Num(n)))
+            }
+            b'a'...b'z' => {
+                let name = rest.split(|c| !is_name_char(c)).next().unwrap();
+                (name.len(), Some(Token::Name(String::from(name))))
+            }
+            _ => {
+                let c = rest.chars().next().unwrap();
+                errors.push(ParseError::new(format!("unexpected character `{}`", c)).at(i));
+                (c.len_utf8(), None)
+            }
+        };
+        if let Some(token) = token {
+            tokens.push((i, token));
+        }
+        i += len;
+    }
+    tokens.push((bytes.len(), Token::Eof));
+    tokens
+}
+
+// Lexes a quoted string (a quote followed by any characters followed by a quote) at the start of
+// the input, in which `\"` and `\\` denote a literal quote and a literal backslash. Any other
+// backslash is kept as-is so that regex escapes like `\d` can be written directly. Returns the
+// length of the string in the input, and its contents if it is terminated.
+fn lex_quote(input: &str) -> (usize, Option<String>) {
+    let bytes = input.as_bytes();
+    let mut out = Vec::new();
+    let mut i = 1;
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1)) {
+            (b'"', _) => return (i + 1, Some(String::from_utf8_lossy(&out).into_owned())),
+            (b'\\', Some(&c)) if c == b'"' || c == b'\\' => {
+                out.push(c);
+                i += 2;
+            }
+            (c, _) => {
+                out.push(c);
+                i += 1;
+            }
+        }
+    }
+    (bytes.len(), None)
+}
+
+// Lexes a raw string (`r"..."`, or `r#"..."#` with any number of `#`s for strings containing `"`)
+// at the start of the input, in which backslashes have no special meaning. Returns the same as
+// lex_quote.
+fn lex_raw_quote(input: &str) -> (usize, Option<String>) {
+    let hashes = input[1..].bytes().take_while(|c| *c == b'#').count();
+    if input.as_bytes().get(1 + hashes) != Some(&b'"') {
+        return (1 + hashes, None);
+    }
+    let terminator: String = iter::once('"').chain(iter::repeat('#').take(hashes)).collect();
+    let body = &input[2 + hashes..];
+    match body.find(&terminator) {
+        Some(end) => (2 + hashes + end + terminator.len(), Some(String::from(&body[..end]))),
+        None => (input.len(), None),
+    }
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    errors: Vec<ParseError>,
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].1
+    }
+
+    // Returns the offset of the next token.
+    fn offset(&self) -> usize {
+        self.tokens[self.pos].0
+    }
+
+    // Consumes and returns the next token. The final Eof is never consumed.
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].1.clone();
+        if token != Token::Eof {
+            self.pos += 1;
+        }
+        token
+    }
+
+    // Consumes the next token if it is the given one, and returns whether it was.
+    fn eat(&mut self, token: &Token) -> bool {
+        if self.peek() == token {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    // Records an error at the next token.
+    fn error(&mut self, message: String) {
+        let offset = self.offset();
+        self.errors.push(ParseError::new(message).at(offset));
+    }
+
+    // Skips tokens up to (but not including) the next `,` or `)` which isn't nested inside of
+    // parentheses opened after the error, so that parsing can resume there.
+    fn recover(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                &Token::Eof => return,
+                &Token::Comma | &Token::RParen if depth == 0 => return,
+                &Token::LParen => depth += 1,
+                &Token::RParen => depth -= 1,
+                _ => {}
+            }
+            self.next();
+        }
+    }
+
+    // Consumes the `)` matching a `(` at the given offset, recording an error if it is missing.
+    fn close(&mut self, open: usize) {
+        if self.eat(&Token::RParen) {
+            return;
+        }
+        if self.peek() == &Token::Eof {
+            self.errors.push(ParseError::new("unclosed `(`").at(open));
+            return;
+        }
+        let message = format!("expected `)`, found {}", self.peek());
+        self.error(message);
+        self.recover();
+        self.eat(&Token::RParen);
+    }
+
+    fn expr(&mut self) -> Option<Expr> {
+        let offset = self.offset();
+        let mut operands = vec![self.and_expr()];
+        while self.eat(&Token::OrOr) {
+            operands.push(self.and_expr());
+        }
+        infix("or", offset, operands)
+    }
+
+    fn and_expr(&mut self) -> Option<Expr> {
+        let offset = self.offset();
+        let mut operands = vec![self.unary()];
+        while self.eat(&Token::AndAnd) {
+            operands.push(self.unary());
+        }
+        infix("and", offset, operands)
+    }
+
+    fn unary(&mut self) -> Option<Expr> {
+        let offset = self.offset();
+        if self.eat(&Token::Bang) {
+            return self.unary().map(|operand| {
+                Expr::Call(Call {
+                               name: String::from("not"),
+                               args: vec![operand],
+                               offset: offset,
+                           })
+            });
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Option<Expr> {
+        let offset = self.offset();
+        match self.peek().clone() {
+            Token::LParen => {
+                self.next();
+                let expr = self.expr();
+                self.close(offset);
+                expr
+            }
+            Token::Str(s) => {
+                self.next();
+                Some(Expr::Quote(s))
+            }
+            Token::Num(n) => {
+                self.next();
+                Some(Expr::Num(n))
+            }
+            Token::Name(name) => {
+                self.next();
+                if self.peek() != &Token::LParen {
+                    if let Some(expr) = bare_word(&name, offset) {
+                        return Some(expr);
+                    }
+                }
+                if !self.eat(&Token::LParen) {
+                    let message = format!("expected `(` after `{}`, found {}", name, self.peek());
+                    self.error(message);
+                    self.recover();
+                    return None;
+                }
+                self.args(offset).map(|args| {
+                                          Expr::Call(Call {
+                                                         name: name,
+                                                         args: args,
+                                                         offset: offset,
+                                                     })
+                                      })
+            }
+            token => {
+                // the token is left in place for recovery or for the enclosing expression
+                self.error(format!("expected an expression, found {}", token));
+                self.recover();
+                None
+            }
+        }
+    }
+
+    // Parses the arguments of a call up to and including the closing `)`, given the offset of the
+    // call.
+    fn args(&mut self, offset: usize) -> Option<Vec<Expr>> {
+        if self.eat(&Token::RParen) {
+            return Some(Vec::new());
+        }
+        let mut args = Vec::new();
+        loop {
+            args.push(self.expr());
+            if self.eat(&Token::Comma) {
+                continue;
+            }
+            if self.eat(&Token::RParen) {
+                break;
+            }
+            if self.peek() == &Token::Eof {
+                self.errors.push(ParseError::new("unclosed argument list").at(offset));
+                return None;
+            }
+            let message = format!("expected `,` or `)`, found {}", self.peek());
+            self.error(message);
+            self.recover();
+            // the argument is followed by garbage, so it isn't valid even if it parsed
+            args.push(None);
+            if !self.eat(&Token::Comma) {
+                self.eat(&Token::RParen);
+                break;
+            }
+        }
+        args.into_iter().collect()
+    }
+}
+
+// Desugars a chain of operands separated by the same infix operator (e.g., `a && b && c`) to a
+// single call with all of the operands as arguments (e.g., `and(a, b, c)`), located at the given
+// offset. Returns None if any operand is invalid.
+fn infix(name: &str, offset: usize, operands: Vec<Option<Expr>>) -> Option<Expr> {
+    let mut operands: Vec<Expr> = match operands.into_iter().collect() {
+        Some(operands) => operands,
+        None => return None,
+    };
+    if operands.len() == 1 {
+        return operands.pop();
+    }
+    Some(Expr::Call(Call {
+                        name: String::from(name),
+                        args: operands,
+                        offset: offset,
+                    }))
+}
+
+// Desugars a name written without an argument list to the call it is shorthand for, if any.
+fn bare_word(name: &str, offset: usize) -> Option<Expr> {
+    let (name, args) = match name {
+        "tests" => ("test", vec![]),
+        "benches" => ("bench", vec![]),
+        "private" => ("vis", vec![Expr::Quote(String::from("priv"))]),
+        _ => return None,
+    };
+    Some(Expr::Call(Call {
+                        name: String::from(name),
+                        args: args,
+                        offset: offset,
+                    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parses input, panicking if it is invalid, and returns the normalized form.
+    fn normalize(input: &str) -> String {
+        match parse(input) {
+            Ok(expr) => expr.to_string(),
+            Err(errors) => panic!("failed to parse {:?}: {:?}", input, errors),
+        }
+    }
+
+    // Parses input, panicking if it is valid, and returns the offset and message of each error.
+    fn errors(input: &str) -> Vec<(Option<usize>, String)> {
+        match parse(input) {
+            Ok(expr) => panic!("unexpectedly parsed {:?} as {}", input, expr),
+            Err(errors) => errors.into_iter().map(|e| (e.offset, e.message)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_operators() {
+        assert_eq!(normalize("a() && !b() || c()"), "or(and(a(), not(b())), c())");
+        assert_eq!(normalize("a() && (b() || c())"), "and(a(), or(b(), c()))");
+        assert_eq!(normalize("a() && b() && c()"), "and(a(), b(), c())");
+        assert_eq!(normalize("!!a()"), "not(not(a()))");
+        assert_eq!(normalize("((a()))"), "a()");
+    }
+
+    #[test]
+    fn test_calls() {
+        assert_eq!(normalize("name(\"foo\")"), "name(\"foo\")");
+        assert_eq!(normalize("lines(1,2)"), "lines(1, 2)");
+        assert_eq!(normalize("any(a(), all(b(), c()))"), "any(a(), all(b(), c()))");
+        assert_eq!(normalize("f_2()"), "f_2()");
+    }
+
+    #[test]
+    fn test_bare_words() {
+        assert_eq!(normalize("tests || benches"), "or(test(), bench())");
+        assert_eq!(normalize("private"), "vis(\"priv\")");
+        // a bare word followed by an argument list is an ordinary call
+        assert_eq!(normalize("tests(1)"), "tests(1)");
+        assert!(parse("name").is_err());
+    }
+
+    #[test]
+    fn test_strings() {
+        let quote = |input: &str| match parse(input) {
+            Ok(Expr::Quote(s)) => s,
+            other => panic!("expected a string, got {:?}", other),
+        };
+        assert_eq!(quote(r#""a\"b\\c""#), r#"a"b\c"#);
+        // other escapes are kept as-is for regexes
+        assert_eq!(quote(r#""\d+\.rs""#), r"\d+\.rs");
+        assert_eq!(quote(r#"r"\d""#), r"\d");
+        assert_eq!(quote(r###"r##"a"#b"##"###), r##"a"#b"##);
+        assert_eq!(normalize(r#"name("a\"b\\c")"#), r#"name("a\"b\\c")"#);
+    }
+
+    #[test]
+    fn test_whitespace_and_comments() {
+        let input = "# tests only\n  and(\n    test(), # the tests\n    name(\"foo\")\n  )\n";
+        assert_eq!(normalize(input), "and(test(), name(\"foo\"))");
+    }
+
+    #[test]
+    fn test_offsets() {
+        match parse("a() || !b(c())") {
+            Ok(Expr::Call(ref or)) => {
+                assert_eq!((or.name.as_str(), or.offset), ("or", 0));
+                match or.args[1] {
+                    Expr::Call(ref not) => {
+                        assert_eq!((not.name.as_str(), not.offset), ("not", 7));
+                        match not.args[0] {
+                            Expr::Call(ref b) => assert_eq!(b.offset, 8),
+                            ref other => panic!("unexpected {:?}", other),
+                        }
+                    }
+                    ref other => panic!("unexpected {:?}", other),
+                }
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_lex_errors() {
+        assert_eq!(errors("a() & b()"), vec![(Some(4), String::from("expected `&&`"))]);
+        assert_eq!(errors("name(\"foo)"),
+                   vec![(Some(5), String::from("unterminated string")),
+                        (Some(0), String::from("unclosed argument list"))]);
+        assert_eq!(errors("a() $"),
+                   vec![(Some(4), String::from("unexpected character `$`"))]);
+        assert_eq!(errors("lines(1, 99999999999999999999)"),
+                   vec![(Some(9), String::from("number is too large: 99999999999999999999"))]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(errors("a() b()"),
+                   vec![(Some(4), String::from("unexpected `b` after expression"))]);
+        assert_eq!(errors("(a()"), vec![(Some(0), String::from("unclosed `(`"))]);
+        assert_eq!(errors("a() &&"),
+                   vec![(Some(6), String::from("expected an expression, found end of input"))]);
+        assert_eq!(errors("name"),
+                   vec![(Some(4), String::from("expected `(` after `name`, found end of input"))]);
+    }
+
+    #[test]
+    fn test_recovery() {
+        // every independent error is reported
+        assert_eq!(errors("any(a(), , b c(), d(&&))"),
+                   vec![(Some(9), String::from("expected an expression, found `,`")),
+                        (Some(13), String::from("expected `(` after `b`, found `c`")),
+                        (Some(20), String::from("expected an expression, found `&&`"))]);
+    }
+
+    #[test]
+    fn test_error_display() {
+        assert_eq!(ParseError::new("oops").to_string(), "oops");
+        assert_eq!(ParseError::new("oops").at(3).to_string(), "at offset 3: oops");
+        // the first offset is the most precise one
+        assert_eq!(ParseError::new("oops").at(3).at(0).offset, Some(3));
+    }
+
+    #[test]
+    fn test_is_name() {
+        assert!(is_name("test"));
+        assert!(is_name("impl_of2"));
+        assert!(!is_name(""));
+        assert!(!is_name("2a"));
+        assert!(!is_name("_a"));
+        assert!(!is_name("Name"));
+    }
+}
@@ -0,0 +1,231 @@
+use syntax::ast::{Ident, Item, ItemKind, Mac, Mod, Path, PathListItem, ViewPath_, Visibility};
+use syntax::codemap::Spanned;
+use syntax::parse::token::Token;
+use syntax::ptr::P;
+use syntax::tokenstream::{TokenStream, TokenTree};
+use syntax::visit::Visitor;
+use syntax_pos::Span;
+
+use filter::{self, Node};
+use {env_flag, with_item_mut, Removal};
+
+use std::collections::HashSet;
+
+const ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_PRUNE_USES";
+
+// Returns true if the use-pruning pass should be run after filtering.
+pub fn enabled() -> bool {
+    env_flag(ENV_VAR_NAME)
+}
+
+// Collects the identifiers which appear in an AST, including those inside of macro invocations.
+struct IdentCollector(HashSet<String>);
+
+impl IdentCollector {
+    fn collect_tokens(&mut self, stream: TokenStream) {
+        for tree in stream.trees() {
+            match tree {
+                TokenTree::Token(span, Token::Ident(ident)) => self.visit_ident(span, ident),
+                TokenTree::Delimited(_, delimited) => self.collect_tokens(delimited.stream()),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<'a> Visitor<'a> for IdentCollector {
+    fn visit_ident(&mut self, _span: Span, ident: Ident) {
+        self.0.insert(String::from(&ident.name.as_str() as &str));
+    }
+
+    fn visit_mac(&mut self, mac: &'a Mac) {
+        self.collect_tokens(mac.node.stream());
+    }
+}
+
+// Returns the identifiers referenced by a node.
+pub fn node_refs(node: &Node) -> HashSet<String> {
+    let mut collector = IdentCollector(HashSet::new());
+    match node {
+        &Node::Item(item) => collector.visit_item(item),
+        &Node::ForeignItem(item) => collector.visit_foreign_item(item),
+        &Node::ImplItem(item) => collector.visit_impl_item(item),
+        &Node::TraitItem(item) => collector.visit_trait_item(item),
+        &Node::Variant(variant) => filter::walk_variant(&mut collector, variant),
+    }
+    collector.0
+}
+
+// Removes `use` declarations from the root module (and, recursively, its submodules) which either
+// import a removed item or are private and were only used by removed items.
+pub fn prune_uses(md: &mut Mod, removals: &[Removal]) {
+    // stubbed out functions and shims still exist, so they can still be imported
+    let removed = removals.iter()
+        .filter(|r| !r.action.replaces())
+        .map(|r| r.path.clone())
+        .collect();
+    prune_mod(md, &mut Vec::new(), &removed, removals);
+}
+
+// Resolves the path of an import in the given module to a fully qualified path. Imports are
+// relative to the crate root, so only leading `self` and `super` segments need special handling.
+pub fn resolve(module: &[String], path: &Path) -> Vec<String> {
+    let mut resolved = Vec::new();
+    for (i, segment) in path.segments.iter().enumerate() {
+        let name = segment.identifier.name.as_str();
+        if name == "{{root}}" {
+            continue;
+        }
+        if i == 0 && (name == "self" || name == "super") {
+            resolved = module.to_vec();
+        }
+        if name == "super" {
+            resolved.pop();
+        } else if name != "self" {
+            resolved.push(String::from(&name as &str));
+        }
+    }
+    resolved
+}
+
+// The state needed to prune the `use` declarations of a single module.
+struct ModPruner<'a> {
+    // The path of the module, as a sequence of module names.
+    path: Vec<String>,
+    // The fully qualified paths of all removed nodes.
+    removed: &'a HashSet<String>,
+    // The identifiers referenced by nodes removed from this module.
+    removed_refs: HashSet<String>,
+    // The identifiers referenced by items kept in this module (other than `use` declarations).
+    kept_refs: HashSet<String>,
+}
+
+impl<'a> ModPruner<'a> {
+    // Returns true if the fully qualified path names a removed node or an item inside of a
+    // removed module.
+    fn is_removed(&self, path: &[String]) -> bool {
+        (1..path.len() + 1).any(|i| self.removed.contains(&path[..i].join("::")))
+    }
+
+    // Returns true if name was used by removed items, but is not used by any kept item.
+    fn is_unused(&self, name: Ident) -> bool {
+        let name = String::from(&name.name.as_str() as &str);
+        self.removed_refs.contains(&name) && !self.kept_refs.contains(&name)
+    }
+
+    fn resolve(&self, path: &Path) -> Vec<String> {
+        resolve(&self.path, path)
+    }
+
+    // Returns true if the import of target under the name binding should be removed.
+    fn should_remove(&self, target: &[String], binding: Ident, private: bool) -> bool {
+        self.is_removed(target) || (private && self.is_unused(binding))
+    }
+
+    // Prunes a single `use` declaration, and returns true if it should be removed entirely.
+    fn prune_use(&self, item: &mut Item) -> bool {
+        let private = if let Visibility::Inherited = item.vis {
+            true
+        } else {
+            false
+        };
+        let vp = match item.node {
+            ItemKind::Use(ref mut vp) => vp,
+            _ => return false,
+        };
+
+        let span = vp.span;
+        let list = match vp.node {
+            ViewPath_::ViewPathSimple(binding, ref path) => {
+                return self.should_remove(&self.resolve(path), binding, private);
+            }
+            ViewPath_::ViewPathGlob(ref path) => return self.is_removed(&self.resolve(path)),
+            ViewPath_::ViewPathList(ref prefix, ref items) => {
+                let base = self.resolve(prefix);
+                if self.is_removed(&base) {
+                    return true;
+                }
+
+                let mut kept: Vec<PathListItem> = Vec::new();
+                for list_item in items.iter() {
+                    let name = list_item.node.name;
+                    let mut target = base.clone();
+                    let binding = if name.name.as_str() == "self" {
+                        match prefix.segments.last() {
+                            Some(segment) => segment.identifier,
+                            None => name,
+                        }
+                    } else {
+                        target.push(String::from(&name.name.as_str() as &str));
+                        name
+                    };
+                    let binding = list_item.node.rename.unwrap_or(binding);
+                    if !self.should_remove(&target, binding, private) {
+                        kept.push(list_item.clone());
+                    }
+                }
+
+                if kept.is_empty() && !items.is_empty() {
+                    return true;
+                }
+                if kept.len() == items.len() {
+                    return false;
+                }
+                ViewPath_::ViewPathList(prefix.clone(), kept)
+            }
+        };
+
+        *vp = P(Spanned {
+                    node: list,
+                    span: span,
+                });
+        false
+    }
+}
+
+fn prune_mod(md: &mut Mod,
+             path: &mut Vec<String>,
+             removed: &HashSet<String>,
+             removals: &[Removal]) {
+    let module = path.join("::");
+    let mut removed_refs = HashSet::new();
+    for removal in removals.iter().filter(|r| r.module == module) {
+        removed_refs.extend(removal.refs.iter().cloned());
+    }
+    let mut kept_refs = IdentCollector(HashSet::new());
+    for item in md.items.iter() {
+        if let ItemKind::Use(..) = item.node {
+            continue;
+        }
+        kept_refs.visit_item(item);
+    }
+    let pruner = ModPruner {
+        path: path.clone(),
+        removed: removed,
+        removed_refs: removed_refs,
+        kept_refs: kept_refs.0,
+    };
+
+    let mut to_delete = Vec::new();
+    for i in 0..md.items.len() {
+        let delete = with_item_mut(md.items.get_mut(i).unwrap(), |it| {
+            if let ItemKind::Mod(ref mut inner) = it.node {
+                path.push(String::from(&it.ident.name.as_str() as &str));
+                prune_mod(inner, path, removed, removals);
+                path.pop();
+                return false;
+            }
+            pruner.prune_use(it)
+        });
+
+        if delete {
+            to_delete.push(i);
+        }
+    }
+
+    let mut offset = 0;
+    for i in to_delete {
+        md.items.remove(i - offset);
+        offset += 1;
+    }
+}
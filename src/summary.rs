@@ -0,0 +1,86 @@
+// Support for summarizing what the filter did: how many nodes were visited, how many of each kind
+// were removed (or stubbed out, etc.), and how many lines of source the removed nodes spanned.
+
+use syntax::ast::{ForeignItem, Generics, ImplItem, Item, Mac, NodeId, TraitItem, Variant};
+use syntax::ext::base::ExtCtxt;
+use syntax::visit::{self, Visitor};
+use syntax_pos::Span;
+
+use {env_flag, Action, Removal};
+
+use std::collections::BTreeMap;
+
+const ENV_VAR_NAME: &str = "RUST_DISABLE_CODE_SUMMARY";
+
+// Returns true if a summary should be printed after filtering.
+pub fn enabled() -> bool {
+    env_flag(ENV_VAR_NAME)
+}
+
+// Returns the number of nodes the filter can be applied to in root (not counting root itself).
+pub fn count_nodes(root: &Item) -> usize {
+    let mut counter = NodeCounter(0);
+    visit::walk_item(&mut counter, root);
+    counter.0
+}
+
+// Prints a one-line summary, such as `visited 120 nodes; removed 3 (1 mod, 2 struct); stubbed out
+// 10 (10 fn); 340 lines removed`.
+pub fn report(cx: &ExtCtxt, span: Span, visited: usize, removals: &[Removal]) {
+    let mut parts = vec![format!("visited {} nodes", visited)];
+    for &action in &[Action::Remove, Action::Stub, Action::Cfg, Action::Shim] {
+        let mut kinds: BTreeMap<&str, usize> = BTreeMap::new();
+        for removal in removals.iter().filter(|r| r.action == action) {
+            *kinds.entry(removal.kind).or_insert(0) += 1;
+        }
+        if kinds.is_empty() {
+            continue;
+        }
+        let total: usize = kinds.values().sum();
+        let kinds: Vec<_> = kinds.iter().map(|(kind, n)| format!("{} {}", n, kind)).collect();
+        parts.push(format!("{} {} ({})", action.past_tense(), total, kinds.join(", ")));
+    }
+    // stubbed out functions and shims are still there, so their lines aren't counted
+    let lines: usize = removals.iter()
+        .filter(|r| !r.action.replaces())
+        .map(|r| {
+                 let lo = cx.codemap().lookup_char_pos(r.span.lo).line;
+                 let hi = cx.codemap().lookup_char_pos(r.span.hi).line;
+                 hi - lo + 1
+             })
+        .sum();
+    parts.push(format!("{} lines removed", lines));
+    cx.parse_sess.span_diagnostic.span_note_without_error(span, &parts.join("; "));
+}
+
+struct NodeCounter(usize);
+
+impl<'a> Visitor<'a> for NodeCounter {
+    fn visit_item(&mut self, item: &'a Item) {
+        self.0 += 1;
+        visit::walk_item(self, item);
+    }
+
+    fn visit_foreign_item(&mut self, item: &'a ForeignItem) {
+        self.0 += 1;
+        visit::walk_foreign_item(self, item);
+    }
+
+    fn visit_impl_item(&mut self, item: &'a ImplItem) {
+        self.0 += 1;
+        visit::walk_impl_item(self, item);
+    }
+
+    fn visit_trait_item(&mut self, item: &'a TraitItem) {
+        self.0 += 1;
+        visit::walk_trait_item(self, item);
+    }
+
+    fn visit_variant(&mut self, variant: &'a Variant, generics: &'a Generics, item_id: NodeId) {
+        self.0 += 1;
+        visit::walk_variant(self, variant, generics, item_id);
+    }
+
+    // Items generated by macros don't exist yet (and the default implementation panics).
+    fn visit_mac(&mut self, _mac: &'a Mac) {}
+}
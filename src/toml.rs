@@ -0,0 +1,506 @@
+// A parser for the subset of TOML used by the plugin's configuration files: tables (`[a.b]`),
+// arrays of tables (`[[a]]`), and `key = value` pairs whose values are strings (basic, literal, and
+// their multi-line forms), integers, booleans, or arrays. Inline tables, floats, and dates are not
+// supported.
+
+use std::char;
+use std::collections::{BTreeMap, HashSet};
+
+#[derive(Debug)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+pub type Table = BTreeMap<String, Value>;
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        if let &Value::String(ref s) = self {
+            Some(s)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        if let &Value::Array(ref values) = self {
+            Some(values)
+        } else {
+            None
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        if let &Value::Table(ref table) = self {
+            Some(table)
+        } else {
+            None
+        }
+    }
+
+    // Returns the name of the value's type, for use in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            &Value::String(_) => "string",
+            &Value::Integer(_) => "integer",
+            &Value::Boolean(_) => "boolean",
+            &Value::Array(_) => "array",
+            &Value::Table(_) => "table",
+        }
+    }
+}
+
+// Parses a document, returning its top-level table, or a description of the first error
+// (including its line number).
+pub fn parse(input: &str) -> Result<Table, String> {
+    let mut parser = Parser {
+        input: input,
+        pos: 0,
+    };
+    match parser.document() {
+        Ok(table) => Ok(table),
+        Err(err) => Err(format!("line {}: {}", parser.line(), err)),
+    }
+}
+
+// Returns the table at the given path below table, creating any tables which don't exist. A path
+// element naming an array of tables refers to its last element.
+fn table_at<'a>(table: &'a mut Table, path: &[String]) -> Result<&'a mut Table, String> {
+    if path.is_empty() {
+        return Ok(table);
+    }
+    let next = match table.entry(path[0].clone()).or_insert_with(|| Value::Table(Table::new())) {
+        &mut Value::Table(ref mut next) => next,
+        &mut Value::Array(ref mut values) => {
+            match values.last_mut() {
+                Some(&mut Value::Table(ref mut next)) => next,
+                _ => return Err(format!("`{}` is not a table", path[0])),
+            }
+        }
+        _ => return Err(format!("`{}` is not a table", path[0])),
+    };
+    table_at(next, &path[1..])
+}
+
+// Returns true if s starts with a newline, possibly preceded by spaces and tabs.
+fn ends_line(s: &str) -> bool {
+    let s = s.trim_left_matches(|c| c == ' ' || c == '\t');
+    s.starts_with('\n') || s.starts_with("\r\n")
+}
+
+fn is_blank(c: char) -> bool {
+    c == ' ' || c == '\t' || c == '\n' || c == '\r'
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    // Returns the line number of the current position.
+    fn line(&self) -> usize {
+        self.input[..self.pos].matches('\n').count() + 1
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest().chars().next()
+    }
+
+    // Consumes s if the input continues with it, and returns whether it did.
+    fn eat(&mut self, s: &str) -> bool {
+        if self.rest().starts_with(s) {
+            self.pos += s.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, s: &str) -> Result<(), String> {
+        if self.eat(s) {
+            Ok(())
+        } else {
+            Err(format!("expected `{}`", s))
+        }
+    }
+
+    // Skips spaces and tabs, and a comment ending the line.
+    fn skip_space(&mut self) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' => self.pos += 1,
+                '#' => self.pos += self.rest().find('\n').unwrap_or(self.rest().len()),
+                _ => break,
+            }
+        }
+    }
+
+    // Skips whitespace, including newlines, and comments.
+    fn skip_blank(&mut self) {
+        loop {
+            self.skip_space();
+            if !self.eat("\n") && !self.eat("\r\n") {
+                return;
+            }
+        }
+    }
+
+    // Consumes the rest of the line, which must be blank.
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_space();
+        if self.peek().is_none() || self.eat("\n") || self.eat("\r\n") {
+            Ok(())
+        } else {
+            Err(String::from("expected the end of the line"))
+        }
+    }
+
+    fn document(&mut self) -> Result<Table, String> {
+        let mut root = Table::new();
+        // the path of the table which key/value pairs are currently added to
+        let mut current = Vec::new();
+        // the paths of the tables defined by `[...]` headers, which can't be defined again
+        let mut defined = HashSet::new();
+        loop {
+            self.skip_blank();
+            if self.peek().is_none() {
+                return Ok(root);
+            }
+
+            if self.eat("[[") {
+                let mut path = self.key_path()?;
+                self.expect("]]")?;
+                let name = path.pop().unwrap();
+                let parent = table_at(&mut root, &path)?;
+                match parent.entry(name.clone()).or_insert_with(|| Value::Array(Vec::new())) {
+                    &mut Value::Array(ref mut tables) => tables.push(Value::Table(Table::new())),
+                    _ => return Err(format!("`{}` is not an array of tables", name)),
+                }
+                path.push(name);
+                // the new element's tables haven't been defined yet
+                defined.retain(|table: &Vec<String>| !table.starts_with(&path));
+                current = path;
+                self.end_of_line()?;
+            } else if self.eat("[") {
+                let path = self.key_path()?;
+                self.expect("]")?;
+                if !defined.insert(path.clone()) {
+                    return Err(format!("duplicate table `[{}]`", path.join(".")));
+                }
+                table_at(&mut root, &path)?;
+                current = path;
+                self.end_of_line()?;
+            } else {
+                let key = self.key()?;
+                self.skip_space();
+                self.expect("=")?;
+                self.skip_space();
+                let value = self.value()?;
+                let table = table_at(&mut root, &current)?;
+                if table.contains_key(&key) {
+                    return Err(format!("duplicate key `{}`", key));
+                }
+                table.insert(key, value);
+                self.end_of_line()?;
+            }
+        }
+    }
+
+    // Parses a dotted sequence of keys (e.g., `a.b`), surrounded by optional spaces.
+    fn key_path(&mut self) -> Result<Vec<String>, String> {
+        let mut path = Vec::new();
+        loop {
+            self.skip_space();
+            path.push(self.key()?);
+            self.skip_space();
+            if !self.eat(".") {
+                return Ok(path);
+            }
+        }
+    }
+
+    // Parses a bare key (letters, digits, `_`, and `-`) or a quoted key.
+    fn key(&mut self) -> Result<String, String> {
+        if self.peek() == Some('"') {
+            return self.basic_string();
+        }
+        let len = self.rest()
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.rest().len());
+        if len == 0 {
+            return Err(String::from("expected a key"));
+        }
+        let key = String::from(&self.rest()[..len]);
+        self.pos += len;
+        Ok(key)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        let rest = self.rest();
+        if rest.starts_with("\"\"\"") {
+            self.multi_line_string("\"\"\"", true).map(Value::String)
+        } else if rest.starts_with("'''") {
+            self.multi_line_string("'''", false).map(Value::String)
+        } else if rest.starts_with('"') {
+            self.basic_string().map(Value::String)
+        } else if rest.starts_with('\'') {
+            self.literal_string().map(Value::String)
+        } else if self.eat("true") {
+            Ok(Value::Boolean(true))
+        } else if self.eat("false") {
+            Ok(Value::Boolean(false))
+        } else if self.eat("[") {
+            self.array()
+        } else if rest.starts_with(|c: char| c == '+' || c == '-' || c.is_digit(10)) {
+            self.integer()
+        } else {
+            Err(String::from("expected a value"))
+        }
+    }
+
+    // Parses the rest of an array after its opening `[`.
+    fn array(&mut self) -> Result<Value, String> {
+        let mut values = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.eat("]") {
+                return Ok(Value::Array(values));
+            }
+            values.push(self.value()?);
+            self.skip_blank();
+            if !self.eat(",") {
+                self.skip_blank();
+                self.expect("]")?;
+                return Ok(Value::Array(values));
+            }
+        }
+    }
+
+    fn integer(&mut self) -> Result<Value, String> {
+        let len = self.rest()
+            .find(|c: char| !(c == '+' || c == '-' || c == '_' || c.is_digit(10)))
+            .unwrap_or(self.rest().len());
+        let digits: String = self.rest()[..len].chars().filter(|c| *c != '_').collect();
+        self.pos += len;
+        match digits.parse() {
+            Ok(n) => Ok(Value::Integer(n)),
+            Err(_) => Err(format!("invalid integer: {}", digits)),
+        }
+    }
+
+    // Parses a basic string (`"..."`), which may contain escape sequences.
+    fn basic_string(&mut self) -> Result<String, String> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        loop {
+            match self.peek() {
+                None | Some('\n') => return Err(String::from("unterminated string")),
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(s);
+                }
+                Some('\\') => s.push(self.escape()?),
+                Some(c) => {
+                    s.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+    }
+
+    // Parses a literal string (`'...'`), in which backslashes have no special meaning.
+    fn literal_string(&mut self) -> Result<String, String> {
+        self.expect("'")?;
+        match self.rest().find(|c| c == '\'' || c == '\n') {
+            Some(end) if self.rest()[end..].starts_with('\'') => {
+                let s = String::from(&self.rest()[..end]);
+                self.pos += end + 1;
+                Ok(s)
+            }
+            _ => Err(String::from("unterminated string")),
+        }
+    }
+
+    // Parses a multi-line string delimited by the given quotes, processing escape sequences if
+    // escapes is true. A newline immediately following the opening quotes is not included, and
+    // when escapes are processed, a backslash ending a line trims all whitespace (including
+    // newlines) up to the next non-whitespace character.
+    fn multi_line_string(&mut self, quotes: &str, escapes: bool) -> Result<String, String> {
+        self.expect(quotes)?;
+        if !self.eat("\n") {
+            self.eat("\r\n");
+        }
+        let mut s = String::new();
+        loop {
+            if self.eat(quotes) {
+                return Ok(s);
+            }
+            match self.peek() {
+                None => return Err(String::from("unterminated string")),
+                Some('\\') if escapes && ends_line(&self.rest()[1..]) => {
+                    let rest = self.rest()[1..].trim_left_matches(is_blank);
+                    self.pos = self.input.len() - rest.len();
+                }
+                Some('\\') if escapes => s.push(self.escape()?),
+                Some(c) => {
+                    s.push(c);
+                    self.pos += c.len_utf8();
+                }
+            }
+        }
+    }
+
+    // Parses an escape sequence in a basic string.
+    fn escape(&mut self) -> Result<char, String> {
+        self.expect("\\")?;
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Err(String::from("unterminated string")),
+        };
+        self.pos += c.len_utf8();
+        match c {
+            'b' => Ok('\u{8}'),
+            't' => Ok('\t'),
+            'n' => Ok('\n'),
+            'f' => Ok('\u{c}'),
+            'r' => Ok('\r'),
+            '"' => Ok('"'),
+            '\\' => Ok('\\'),
+            'u' | 'U' => {
+                let len = if c == 'u' { 4 } else { 8 };
+                let hex: String = self.rest().chars().take(len).collect();
+                // from_str_radix would also accept a sign or fewer digits
+                let code = if hex.len() == len && hex.chars().all(|c| c.is_digit(16)) {
+                    u32::from_str_radix(&hex, 16).ok().and_then(char::from_u32)
+                } else {
+                    None
+                };
+                match code {
+                    Some(c) => {
+                        self.pos += len;
+                        Ok(c)
+                    }
+                    None => Err(format!("invalid unicode escape: \\{}{}", c, hex)),
+                }
+            }
+            _ => Err(format!("invalid escape sequence: \\{}", c)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Parses input, panicking if it is invalid.
+    fn parse_ok(input: &str) -> Table {
+        match parse(input) {
+            Ok(table) => table,
+            Err(err) => panic!("failed to parse {:?}: {}", input, err),
+        }
+    }
+
+    // Parses input, panicking if it is valid, and returns the error.
+    fn parse_err(input: &str) -> String {
+        match parse(input) {
+            Ok(table) => panic!("unexpectedly parsed {:?} as {:?}", input, table),
+            Err(err) => err,
+        }
+    }
+
+    // Parses a document consisting of `key = <input>`, and returns the string value of key.
+    fn string(input: &str) -> String {
+        let table = parse_ok(&format!("key = {}", input));
+        match table.get("key") {
+            Some(&Value::String(ref s)) => s.clone(),
+            other => panic!("expected a string, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_values() {
+        let table = parse_ok("a = 1\nb = -2_000\nc = true\nd = false\ne = [1, \"x\", [],]\n");
+        assert_eq!(format!("{:?}", table.get("a")), "Some(Integer(1))");
+        assert_eq!(format!("{:?}", table.get("b")), "Some(Integer(-2000))");
+        assert_eq!(format!("{:?}", table.get("c")), "Some(Boolean(true))");
+        assert_eq!(format!("{:?}", table.get("d")), "Some(Boolean(false))");
+        assert_eq!(format!("{:?}", table.get("e")),
+                   "Some(Array([Integer(1), String(\"x\"), Array([])]))");
+    }
+
+    #[test]
+    fn test_strings() {
+        assert_eq!(string(r#""a\tb\"c\\d\u00e9""#), "a\tb\"c\\d\u{e9}");
+        assert_eq!(string(r"'C:\path'"), r"C:\path");
+        assert_eq!(string("\"\"\"\nline 1\nline 2\"\"\""), "line 1\nline 2");
+        assert_eq!(string("'''\n\\d+\n'''"), "\\d+\n");
+    }
+
+    #[test]
+    fn test_unicode_escapes() {
+        assert_eq!(string(r#""\U0001F600""#), "\u{1f600}");
+        assert_eq!(parse_err(r#"a = "\u12""#), "line 1: invalid unicode escape: \\u12\"");
+        assert_eq!(parse_err(r#"a = "\u+123""#), "line 1: invalid unicode escape: \\u+123");
+        assert_eq!(parse_err(r#"a = "\ud800""#), "line 1: invalid unicode escape: \\ud800");
+        assert_eq!(parse_err("a = \"\\u12"), "line 1: invalid unicode escape: \\u12");
+    }
+
+    #[test]
+    fn test_line_ending_backslash() {
+        assert_eq!(string("\"\"\"\nthe quick \\\n    brown \\  \r\n\n   fox\"\"\""),
+                   "the quick brown fox");
+        // literal strings have no escapes
+        assert_eq!(string("'''a \\\nb'''"), "a \\\nb");
+        // a backslash followed by anything else is still an escape sequence
+        assert_eq!(parse_err("key = \"\"\"a \\ b\"\"\""), "line 1: invalid escape sequence: \\ ");
+    }
+
+    #[test]
+    fn test_tables() {
+        let table = parse_ok("a = 1 # comment\n\n[b]\nc = 2\n\n[b.d]\ne = 3\n[\"f g\"]\n");
+        assert_eq!(format!("{:?}", table),
+                   "{\"a\": Integer(1), \"b\": Table({\"c\": Integer(2), \"d\": \
+                    Table({\"e\": Integer(3)})}), \"f g\": Table({})}");
+        // a table created implicitly by a header for a table inside of it can be defined later
+        parse_ok("[a.b]\n[a]\n");
+    }
+
+    #[test]
+    fn test_arrays_of_tables() {
+        let table = parse_ok("[[rule]]\nkind = \"fn\"\n[rule.sub]\n[[rule]]\nkind = \"mod\"\n\
+                              [rule.sub]\n");
+        let rules = table.get("rule").and_then(Value::as_array).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert_eq!(rules[1].as_table().and_then(|rule| rule.get("kind")).and_then(Value::as_str),
+                   Some("mod"));
+        assert_eq!(parse_err("a = 1\n[[a]]\n"), "line 2: `a` is not an array of tables");
+    }
+
+    #[test]
+    fn test_duplicates() {
+        assert_eq!(parse_err("a = 1\na = 2\n"), "line 2: duplicate key `a`");
+        assert_eq!(parse_err("[a]\nb = 1\n[a]\n"), "line 3: duplicate table `[a]`");
+        assert_eq!(parse_err("[a.b]\n[a . b]\n"), "line 2: duplicate table `[a.b]`");
+        assert_eq!(parse_err("[[a]]\n[a.b]\n[a.b]\n"), "line 3: duplicate table `[a.b]`");
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(parse_err("a = \"b\n"), "line 1: unterminated string");
+        assert_eq!(parse_err("a = 1 2\n"), "line 1: expected the end of the line");
+        assert_eq!(parse_err("a = \n"), "line 1: expected a value");
+        assert_eq!(parse_err("\n\na = 1.5\n"), "line 3: expected the end of the line");
+        assert_eq!(parse_err("[a\n"), "line 1: expected `]`");
+        assert_eq!(parse_err("a = 1\n[a.b]\n"), "line 2: `a` is not a table");
+        assert_eq!(parse_err("a = \"\\q\"\n"), "line 1: invalid escape sequence: \\q");
+    }
+}
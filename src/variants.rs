@@ -0,0 +1,83 @@
+// Warnings about the patterns which still refer to removed enum variants, and so will no longer
+// compile. As elsewhere, variants are matched by name: a pattern refers to a variant if its path
+// ends with the names of the enum and the variant (e.g., `Foo::Bar` or `foo::Foo::Bar`). Patterns
+// naming an imported variant on its own (e.g., `Bar` after `use Foo::*`) are not detected.
+
+use syntax::ast::{Item, Mac, Pat, PatKind, Path};
+use syntax::ext::base::ExtCtxt;
+use syntax::visit::{self, Visitor};
+
+use {is_cfg_false, Removal};
+
+use std::collections::HashSet;
+
+// Warns about every pattern in root which refers to a removed variant.
+pub fn check_patterns(cx: &ExtCtxt, root: &Item, removals: &[Removal]) {
+    let removed: HashSet<(String, String)> = removals.iter()
+        .filter(|r| r.kind == "variant" && !r.action.replaces())
+        .filter_map(|r| {
+                        let mut names = r.path.rsplit("::");
+                        match (names.next(), names.next()) {
+                            (Some(variant), Some(enm)) => {
+                                Some((String::from(enm), String::from(variant)))
+                            }
+                            _ => None,
+                        }
+                    })
+        .collect();
+    if removed.is_empty() {
+        return;
+    }
+    let mut checker = PatternChecker {
+        cx: cx,
+        removed: &removed,
+    };
+    checker.visit_item(root);
+}
+
+struct PatternChecker<'a, 'b: 'a> {
+    cx: &'a ExtCtxt<'b>,
+    // The names of the removed variants, each paired with the name of its enum.
+    removed: &'a HashSet<(String, String)>,
+}
+
+impl<'a, 'b> PatternChecker<'a, 'b> {
+    fn check_path(&self, pat: &Pat, path: &Path) {
+        let len = path.segments.len();
+        if len < 2 {
+            return;
+        }
+        let enm = String::from(&path.segments[len - 2].identifier.name.as_str() as &str);
+        let variant = String::from(&path.segments[len - 1].identifier.name.as_str() as &str);
+        let key = (enm, variant);
+        if self.removed.contains(&key) {
+            self.cx.span_warn(pat.span,
+                              &format!("pattern refers to removed variant `{}::{}`",
+                                       key.0,
+                                       key.1));
+        }
+    }
+}
+
+impl<'a, 'b, 'c> Visitor<'c> for PatternChecker<'a, 'b> {
+    fn visit_item(&mut self, item: &'c Item) {
+        // disabled items are never compiled
+        if !item.attrs.iter().any(is_cfg_false) {
+            visit::walk_item(self, item);
+        }
+    }
+
+    fn visit_pat(&mut self, pat: &'c Pat) {
+        match pat.node {
+            PatKind::Path(_, ref path) |
+            PatKind::TupleStruct(ref path, ..) |
+            PatKind::Struct(ref path, ..) => self.check_path(pat, path),
+            _ => {}
+        }
+        visit::walk_pat(self, pat);
+    }
+
+    // The patterns inside of unexpanded macro invocations can't be checked (and the default
+    // implementation panics).
+    fn visit_mac(&mut self, _mac: &'c Mac) {}
+}
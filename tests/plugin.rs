@@ -0,0 +1,117 @@
+// Tests which compile small crates with the plugin and check what it removed, as recorded by
+// RUST_DISABLE_CODE_REPORT.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::process::Command;
+
+// Compiles source as a library crate named name with the plugin and the given filter, with the
+// given variables set in the environment, and returns the lines of the report. Panics if the crate
+// doesn't compile.
+fn compile(name: &str, filter: &str, vars: &[(&str, &str)], source: &str) -> Vec<String> {
+    // the test binary is built into the same directory as the plugin
+    let exe = env::current_exe().unwrap();
+    let deps = exe.parent().unwrap();
+    let dir = env::temp_dir().join(format!("disable_code-test-{}", name));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("lib.rs");
+    let report = dir.join("report");
+    let _ = fs::remove_file(&report);
+    let header = format!("#![feature(plugin)]\n#![plugin(disable_code(filter = {:?}))]\n",
+                         filter);
+    File::create(&path).unwrap().write_all((header + source).as_bytes()).unwrap();
+
+    let mut command = Command::new(env::var("RUSTC").unwrap_or_else(|_| String::from("rustc")));
+    command.arg("--crate-type=lib")
+        .arg("--crate-name")
+        .arg(name)
+        .arg("-L")
+        .arg(deps)
+        .arg("--out-dir")
+        .arg(&dir)
+        .arg(&path)
+        .env_remove("RUST_DISABLE_CODE_FILTER")
+        .env("RUST_DISABLE_CODE_REPORT", &report);
+    for &(var, value) in vars {
+        command.env(var, value);
+    }
+    let output = command.output().unwrap();
+    assert!(output.status.success(),
+            "compiling {} failed:\n{}",
+            name,
+            String::from_utf8_lossy(&output.stderr));
+
+    let mut contents = String::new();
+    File::open(&report).unwrap().read_to_string(&mut contents).unwrap();
+    contents.lines().map(String::from).collect()
+}
+
+// Methods of impls and defaults of traits which the filter matches, but which are still called,
+// are kept.
+#[test]
+fn test_preserve_methods() {
+    let source = r#"
+#![allow(dead_code)]
+
+pub trait Tr {
+    fn required(&self) -> u32;
+    fn helper_default(&self) -> u32 { 1 }
+    fn helper_unused(&self) -> u32 { 2 }
+}
+
+pub struct S;
+
+impl Tr for S {
+    fn required(&self) -> u32 { 0 }
+}
+
+impl S {
+    fn helper_method(&self) -> u32 { 3 }
+    fn helper_dead(&self) -> u32 { 4 }
+}
+
+pub fn user(s: &S) -> u32 {
+    s.helper_method() + s.helper_default()
+}
+"#;
+    assert_eq!(compile("preserve_methods",
+                       "prefix(\"helper\")",
+                       &[("RUST_DISABLE_CODE_PRESERVE_DEPS", "1")],
+                       source),
+               vec!["fn preserve_methods::Tr::helper_unused",
+                    "fn preserve_methods::S::helper_dead"]);
+}
+
+// Function bodies don't count as modules for depth() and parent(), although the items declared in
+// them are named as though they did.
+#[test]
+fn test_fn_scopes() {
+    let source = r#"
+#![allow(dead_code)]
+
+fn outer() -> u32 {
+    struct Helper;
+    fn inner() -> u32 { 1 }
+    inner()
+}
+
+pub mod m {
+    pub fn f() -> u32 {
+        fn nested() -> u32 { 2 }
+        nested()
+    }
+    pub fn g() {}
+}
+"#;
+    assert_eq!(compile("fn_scopes_depth", "depth(1)", &[], source),
+               vec!["fn fn_scopes_depth::m::f", "fn fn_scopes_depth::m::g"]);
+    assert!(compile("fn_scopes_parent", "parent(\"outer\")", &[], source).is_empty());
+    assert_eq!(compile("fn_scopes_path",
+                       "path(\"^outer::Helper$\") || parent(\"m\")",
+                       &[],
+                       source),
+               vec!["struct fn_scopes_path::outer::Helper",
+                    "fn fn_scopes_path::m::f",
+                    "fn fn_scopes_path::m::g"]);
+}